@@ -0,0 +1,393 @@
+use crate::range;
+use crate::s3::{GetObjectResult, ObjectListing, ObjectMetadata, S3};
+use aws_sdk_s3::error::SdkError;
+use aws_sdk_s3::operation::get_object::GetObjectError;
+use aws_sdk_s3::operation::head_bucket::HeadBucketError;
+use aws_sdk_s3::operation::head_object::HeadObjectError;
+use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Error;
+use aws_smithy_types::byte_stream::ByteStream;
+use bytes::Bytes;
+use moka::future::Cache;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// Total size of cached object bodies in bytes. The cache is disabled when zero.
+    pub capacity: u64,
+    pub max_object_size: u64,
+    /// Freshness lifetime of entries whose object has no `Cache-Control: max-age`.
+    pub ttl: Duration,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    metadata: ObjectMetadata,
+    body: Bytes,
+    fetched_at: Instant,
+    ttl: Duration,
+}
+
+impl CacheEntry {
+    fn is_fresh(&self) -> bool {
+        self.fetched_at.elapsed() < self.ttl
+    }
+
+    fn revalidated(&self) -> Self {
+        Self {
+            metadata: self.metadata.clone(),
+            body: self.body.clone(),
+            fetched_at: Instant::now(),
+            ttl: self.ttl,
+        }
+    }
+
+    fn to_result(&self, byte_range: Option<&str>) -> Option<GetObjectResult> {
+        let Some(byte_range) = byte_range else {
+            return Some(GetObjectResult::new(
+                ByteStream::from(self.body.clone()),
+                None,
+                self.metadata.clone(),
+            ));
+        };
+
+        let total = self.body.len() as u64;
+        let (start, end) = range::resolve_single(byte_range, total)?;
+        let body = self.body.slice(start as usize..=end as usize);
+        let metadata = self.metadata.clone().with_content_length(body.len() as i64);
+
+        Some(GetObjectResult::new(
+            ByteStream::from(body),
+            Some(range::content_range(start, end, total)),
+            metadata,
+        ))
+    }
+}
+
+/// Returns how long an object may be served from a cache without revalidation,
+/// or `None` if its `Cache-Control` forbids storing it in a shared cache.
+pub fn freshness_lifetime(metadata: &ObjectMetadata, default_ttl: Duration) -> Option<Duration> {
+    let Some(cache_control) = metadata.cache_control() else {
+        return Some(default_ttl);
+    };
+
+    let mut ttl = default_ttl;
+    for directive in cache_control.split(',').map(str::trim) {
+        let directive = directive.to_ascii_lowercase();
+        if directive == "no-store" || directive == "private" {
+            return None;
+        }
+        if directive == "no-cache" {
+            ttl = Duration::ZERO;
+        } else if let Some(max_age) = directive.strip_prefix("max-age=") {
+            if let Ok(max_age) = max_age.parse() {
+                ttl = Duration::from_secs(max_age);
+            }
+        }
+    }
+    Some(ttl)
+}
+
+/// An [`S3`] implementation that keeps small objects in a size-bounded in-memory cache.
+/// Stale entries are revalidated against the ETag returned by HeadObject.
+///
+/// Entries are keyed by bucket and key only. The content encoding is already part of the key,
+/// because precompressed variants are stored as their own objects (e.g. `app.js.br`), and
+/// on-the-fly compression happens above this layer on the bodies it returns.
+#[derive(Debug, Clone)]
+pub struct CachedS3<T> {
+    inner: T,
+    config: CacheConfig,
+    cache: Option<Cache<(String, String), Arc<CacheEntry>>>,
+}
+
+impl<T> CachedS3<T> {
+    pub fn new(inner: T, config: CacheConfig) -> Self {
+        let cache = (config.capacity > 0).then(|| {
+            Cache::builder()
+                .max_capacity(config.capacity)
+                .weigher(
+                    |(bucket, key): &(String, String), entry: &Arc<CacheEntry>| {
+                        (bucket.len() + key.len() + entry.body.len())
+                            .try_into()
+                            .unwrap_or(u32::MAX)
+                    },
+                )
+                .build()
+        });
+
+        Self {
+            inner,
+            config,
+            cache,
+        }
+    }
+}
+
+impl<T> CachedS3<T>
+where
+    T: S3 + Send + Sync,
+{
+    async fn lookup(
+        &self,
+        cache: &Cache<(String, String), Arc<CacheEntry>>,
+        bucket: &str,
+        key: &str,
+    ) -> Option<Arc<CacheEntry>> {
+        let cache_key = (bucket.to_string(), key.to_string());
+        let entry = cache.get(&cache_key).await?;
+        if entry.is_fresh() {
+            return Some(entry);
+        }
+
+        match self.inner.head_object(bucket, key).await {
+            Ok(head) if head.e_tag().is_some() && head.e_tag() == entry.metadata.e_tag() => {
+                let entry = Arc::new(entry.revalidated());
+                cache.insert(cache_key, entry.clone()).await;
+                Some(entry)
+            }
+            _ => {
+                cache.invalidate(&cache_key).await;
+                None
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<T> S3 for CachedS3<T>
+where
+    T: S3 + Send + Sync,
+{
+    async fn get_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        range: Option<String>,
+    ) -> Result<GetObjectResult, SdkError<GetObjectError>> {
+        let Some(cache) = &self.cache else {
+            return self.inner.get_object(bucket, key, range).await;
+        };
+
+        if let Some(entry) = self.lookup(cache, bucket, key).await {
+            if let Some(result) = entry.to_result(range.as_deref()) {
+                tracing::debug!("cache hit: s3://{}/{}", bucket, key);
+                return Ok(result);
+            }
+        }
+        if range.is_some() {
+            return self.inner.get_object(bucket, key, range).await;
+        }
+
+        let resp = self.inner.get_object(bucket, key, None).await?;
+        let cacheable = resp
+            .metadata()
+            .content_length()
+            .is_some_and(|length| length >= 0 && length as u64 <= self.config.max_object_size);
+        let Some(ttl) = freshness_lifetime(resp.metadata(), self.config.ttl).filter(|_| cacheable)
+        else {
+            return Ok(resp);
+        };
+
+        let metadata = resp.metadata().clone();
+        let body = match resp.body().collect().await {
+            Ok(body) => body.into_bytes(),
+            Err(e) => {
+                tracing::warn!(
+                    "failed to collect body for cache: s3://{}/{}: {:?}",
+                    bucket,
+                    key,
+                    e
+                );
+                return self.inner.get_object(bucket, key, None).await;
+            }
+        };
+
+        let entry = Arc::new(CacheEntry {
+            metadata,
+            body,
+            fetched_at: Instant::now(),
+            ttl,
+        });
+        cache
+            .insert((bucket.to_string(), key.to_string()), entry.clone())
+            .await;
+
+        Ok(GetObjectResult::new(
+            ByteStream::from(entry.body.clone()),
+            None,
+            entry.metadata.clone(),
+        ))
+    }
+
+    async fn head_object(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> Result<ObjectMetadata, SdkError<HeadObjectError>> {
+        if let Some(cache) = &self.cache {
+            let cache_key = (bucket.to_string(), key.to_string());
+            if let Some(entry) = cache.get(&cache_key).await.filter(|e| e.is_fresh()) {
+                return Ok(entry.metadata.clone());
+            }
+        }
+
+        self.inner.head_object(bucket, key).await
+    }
+
+    async fn head_bucket(
+        &self,
+        bucket: &str,
+        expected_bucket_owner: &str,
+    ) -> Result<(), SdkError<HeadBucketError>> {
+        self.inner.head_bucket(bucket, expected_bucket_owner).await
+    }
+
+    async fn list_objects(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        continuation_token: Option<String>,
+    ) -> Result<ObjectListing, SdkError<ListObjectsV2Error>> {
+        self.inner
+            .list_objects(bucket, prefix, continuation_token)
+            .await
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::s3::tests::{Counting, Objects};
+
+    pub(crate) const BODY: &str = "hello world";
+
+    /// Holds [`BODY`] under the keys the cache tests read.
+    pub(crate) fn objects(e_tag: &str, cache_control: Option<&str>) -> Objects {
+        let builder = ObjectMetadata::builder().e_tag(e_tag);
+        let metadata = match cache_control {
+            Some(cache_control) => builder.cache_control(cache_control).build(),
+            None => builder.build(),
+        };
+        ["key", "a", "b", "c"]
+            .into_iter()
+            .fold(Objects::default(), |objects, key| {
+                objects.with(key, BODY, metadata.clone())
+            })
+    }
+
+    pub(crate) fn fake(e_tag: &str) -> Counting {
+        Counting::new(objects(e_tag, None))
+    }
+
+    fn config(ttl: Duration) -> CacheConfig {
+        CacheConfig {
+            capacity: 1024,
+            max_object_size: 100,
+            ttl,
+        }
+    }
+
+    async fn body(result: GetObjectResult) -> Bytes {
+        result.body().collect().await.unwrap().into_bytes()
+    }
+
+    #[tokio::test]
+    async fn test_cache_hit() {
+        let fake = fake("\"abc\"");
+        let cached = CachedS3::new(fake.clone(), config(Duration::from_secs(60)));
+
+        for _ in 0..3 {
+            let result = cached.get_object("bucket", "key", None).await.unwrap();
+            assert_eq!(body(result).await, BODY);
+        }
+        assert_eq!(fake.calls.get_object(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_cache_range() {
+        let fake = fake("\"abc\"");
+        let cached = CachedS3::new(fake.clone(), config(Duration::from_secs(60)));
+        cached.get_object("bucket", "key", None).await.unwrap();
+
+        let result = cached
+            .get_object("bucket", "key", Some("bytes=6-".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(result.content_range(), Some("bytes 6-10/11"));
+        assert_eq!(result.metadata().content_length(), Some(5));
+        assert_eq!(body(result).await, "world");
+        assert_eq!(fake.calls.get_object(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_cache_revalidate() {
+        let fake = fake("\"abc\"");
+        let cached = CachedS3::new(fake.clone(), config(Duration::ZERO));
+
+        cached.get_object("bucket", "key", None).await.unwrap();
+        cached.get_object("bucket", "key", None).await.unwrap();
+        assert_eq!(fake.calls.get_object(), 1);
+        assert_eq!(fake.calls.head_object(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_cache_revalidate_changed() {
+        let fake = fake("\"abc\"");
+        let cached = CachedS3::new(fake.clone(), config(Duration::ZERO));
+        cached.get_object("bucket", "key", None).await.unwrap();
+
+        let changed = Counting {
+            objects: objects("\"xyz\"", None),
+            ..fake.clone()
+        };
+        let cached = CachedS3 {
+            inner: changed,
+            ..cached
+        };
+        cached.get_object("bucket", "key", None).await.unwrap();
+        assert_eq!(fake.calls.get_object(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_cache_no_store() {
+        let fake = Counting::new(objects("\"abc\"", Some("no-store")));
+        let cached = CachedS3::new(fake.clone(), config(Duration::from_secs(60)));
+
+        cached.get_object("bucket", "key", None).await.unwrap();
+        cached.get_object("bucket", "key", None).await.unwrap();
+        assert_eq!(fake.calls.get_object(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_cache_too_large() {
+        let fake = fake("\"abc\"");
+        let cached = CachedS3::new(
+            fake.clone(),
+            CacheConfig {
+                max_object_size: 5,
+                ..config(Duration::from_secs(60))
+            },
+        );
+
+        cached.get_object("bucket", "key", None).await.unwrap();
+        cached.get_object("bucket", "key", None).await.unwrap();
+        assert_eq!(fake.calls.get_object(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_cache_disabled() {
+        let fake = fake("\"abc\"");
+        let cached = CachedS3::new(
+            fake.clone(),
+            CacheConfig {
+                capacity: 0,
+                ..config(Duration::from_secs(60))
+            },
+        );
+
+        cached.get_object("bucket", "key", None).await.unwrap();
+        cached.get_object("bucket", "key", None).await.unwrap();
+        assert_eq!(fake.calls.get_object(), 2);
+    }
+}
@@ -0,0 +1,454 @@
+use crate::s3::{GetObjectResult, ObjectListing, ObjectMetadata, S3};
+use aws_sdk_s3::error::SdkError;
+use aws_sdk_s3::operation::get_object::GetObjectError;
+use aws_sdk_s3::operation::head_bucket::HeadBucketError;
+use aws_sdk_s3::operation::head_object::HeadObjectError;
+use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Error;
+use aws_sdk_s3::types::error::{InvalidObjectState, NoSuchKey};
+use aws_smithy_runtime_api::http::{Response, StatusCode};
+use aws_smithy_types::body::SdkBody;
+use aws_smithy_types::byte_stream::ByteStream;
+use aws_smithy_types::error::ErrorMetadata;
+use bytes::Bytes;
+use futures_util::future::{BoxFuture, Shared};
+use futures_util::FutureExt;
+use hyper::body::{Body, Frame};
+use std::collections::HashMap;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+
+type FlightKey = (String, String, Option<String>);
+type Flight = Shared<BoxFuture<'static, Option<Arc<Outcome>>>>;
+
+/// A service error that can be rebuilt for every waiter of a flight.
+#[derive(Debug, Clone)]
+enum SharedError {
+    NoSuchKey(NoSuchKey),
+    InvalidObjectState(InvalidObjectState),
+    Other(ErrorMetadata),
+}
+
+impl SharedError {
+    fn from_sdk_error(e: &SdkError<GetObjectError>) -> Option<(Self, StatusCode)> {
+        let SdkError::ServiceError(context) = e else {
+            return None;
+        };
+        let error = match context.err() {
+            GetObjectError::NoSuchKey(e) => SharedError::NoSuchKey(e.clone()),
+            GetObjectError::InvalidObjectState(e) => SharedError::InvalidObjectState(e.clone()),
+            e => SharedError::Other(e.meta().clone()),
+        };
+        Some((error, context.raw().status()))
+    }
+
+    fn to_sdk_error(&self, status: StatusCode) -> SdkError<GetObjectError> {
+        let source = match self {
+            SharedError::NoSuchKey(e) => GetObjectError::NoSuchKey(e.clone()),
+            SharedError::InvalidObjectState(e) => GetObjectError::InvalidObjectState(e.clone()),
+            SharedError::Other(meta) => GetObjectError::generic(meta.clone()),
+        };
+        SdkError::service_error(source, Response::new(status, SdkBody::empty()))
+    }
+}
+
+/// The body of a shared fetch. Chunks are kept as they arrive, so that waiters joining late
+/// replay them before following the rest of the body.
+#[derive(Debug, Default)]
+struct SharedBody {
+    state: Mutex<BodyState>,
+}
+
+#[derive(Debug, Default)]
+struct BodyState {
+    chunks: Vec<Bytes>,
+    /// `None` while the body is still arriving.
+    end: Option<Result<(), String>>,
+    waiters: Vec<mpsc::UnboundedSender<Result<Bytes, String>>>,
+}
+
+impl SharedBody {
+    fn subscribe(&self) -> ByteStream {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let mut state = self.state.lock().unwrap();
+        for chunk in &state.chunks {
+            let _ = sender.send(Ok(chunk.clone()));
+        }
+        match &state.end {
+            None => state.waiters.push(sender),
+            Some(Err(e)) => {
+                let _ = sender.send(Err(e.clone()));
+            }
+            Some(Ok(())) => {}
+        }
+
+        ByteStream::new(SdkBody::from_body_1_x(SharedBodyReader(receiver)))
+    }
+
+    fn push(&self, chunk: Bytes) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .waiters
+            .retain(|waiter| waiter.send(Ok(chunk.clone())).is_ok());
+        state.chunks.push(chunk);
+    }
+
+    fn finish(&self, result: Result<(), String>) {
+        let mut state = self.state.lock().unwrap();
+        if let Err(e) = &result {
+            for waiter in &state.waiters {
+                let _ = waiter.send(Err(e.clone()));
+            }
+        }
+        state.waiters.clear();
+        state.end = Some(result);
+    }
+
+    /// Reads the body from S3. The flight ends once the body is complete.
+    async fn fill(self: Arc<Self>, mut body: ByteStream, _guard: FlightGuard) {
+        loop {
+            match body.try_next().await {
+                Ok(Some(chunk)) => self.push(chunk),
+                Ok(None) => return self.finish(Ok(())),
+                Err(e) => {
+                    tracing::warn!("failed to read shared body: {:?}", e);
+                    return self.finish(Err(e.to_string()));
+                }
+            }
+        }
+    }
+}
+
+/// The copy of a [`SharedBody`] streamed to one waiter.
+struct SharedBodyReader(mpsc::UnboundedReceiver<Result<Bytes, String>>);
+
+impl Body for SharedBodyReader {
+    type Data = Bytes;
+    type Error = aws_smithy_types::body::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        self.0.poll_recv(cx).map(|chunk| {
+            chunk.map(|chunk| {
+                chunk
+                    .map(Frame::data)
+                    .map_err(|e| io::Error::other(e).into())
+            })
+        })
+    }
+}
+
+/// The result of an in-flight fetch, handed to every waiter.
+#[derive(Debug)]
+enum Outcome {
+    Object {
+        body: Arc<SharedBody>,
+        content_range: Option<String>,
+        metadata: ObjectMetadata,
+    },
+    Error(SharedError, StatusCode),
+    /// A result that cannot be shared, e.g. a large streaming body or a transport error.
+    /// The first waiter takes it and the others fetch on their own.
+    Exclusive(Mutex<Option<Result<GetObjectResult, SdkError<GetObjectError>>>>),
+}
+
+impl Outcome {
+    /// Shares the result of a fetch with the waiters as soon as the response arrives. A shared
+    /// body keeps arriving in the background and the flight ends once it is complete.
+    fn new(
+        result: Result<GetObjectResult, SdkError<GetObjectError>>,
+        max_object_size: u64,
+        guard: FlightGuard,
+    ) -> Self {
+        let resp = match result {
+            Ok(resp) => resp,
+            Err(e) => {
+                return match SharedError::from_sdk_error(&e) {
+                    Some((error, status)) => Outcome::Error(error, status),
+                    None => Outcome::Exclusive(Mutex::new(Some(Err(e)))),
+                }
+            }
+        };
+
+        let shareable = resp
+            .metadata()
+            .content_length()
+            .is_some_and(|length| length >= 0 && length as u64 <= max_object_size);
+        if !shareable {
+            return Outcome::Exclusive(Mutex::new(Some(Ok(resp))));
+        }
+
+        let content_range = resp.content_range().map(str::to_string);
+        let metadata = resp.metadata().clone();
+        let body = Arc::new(SharedBody::default());
+        tokio::spawn(body.clone().fill(resp.body(), guard));
+
+        Outcome::Object {
+            body,
+            content_range,
+            metadata,
+        }
+    }
+}
+
+/// Removes the flight from the table once its fetch has finished, even if the fetch panicked.
+struct FlightGuard {
+    in_flight: Arc<Mutex<HashMap<FlightKey, Flight>>>,
+    key: FlightKey,
+}
+
+impl Drop for FlightGuard {
+    fn drop(&mut self) {
+        if let Ok(mut in_flight) = self.in_flight.lock() {
+            in_flight.remove(&self.key);
+        }
+    }
+}
+
+/// An [`S3`] implementation that shares a single GetObject among concurrent requests
+/// for the same bucket, key and range.
+///
+/// The fetch runs on its own task, so a waiter going away does not abort it for the others.
+#[derive(Debug, Clone)]
+pub struct CoalescingS3<T> {
+    inner: T,
+    max_object_size: u64,
+    in_flight: Arc<Mutex<HashMap<FlightKey, Flight>>>,
+}
+
+impl<T> CoalescingS3<T> {
+    /// Bodies are streamed to every waiter as they arrive. Bodies larger than `max_object_size`
+    /// are not kept for replay and only reach the first waiter.
+    /// Coalescing is disabled when it is zero.
+    pub fn new(inner: T, max_object_size: u64) -> Self {
+        Self {
+            inner,
+            max_object_size,
+            in_flight: Arc::default(),
+        }
+    }
+}
+
+impl<T> CoalescingS3<T>
+where
+    T: S3 + Clone + Send + Sync + 'static,
+{
+    fn start(&self, key: FlightKey) -> Flight {
+        let inner = self.inner.clone();
+        let max_object_size = self.max_object_size;
+        let guard = FlightGuard {
+            in_flight: self.in_flight.clone(),
+            key,
+        };
+
+        let handle = tokio::spawn(async move {
+            let (bucket, key, range) = guard.key.clone();
+            let result = inner.get_object(&bucket, &key, range).await;
+            Arc::new(Outcome::new(result, max_object_size, guard))
+        });
+
+        async move { handle.await.ok() }.boxed().shared()
+    }
+}
+
+#[async_trait::async_trait]
+impl<T> S3 for CoalescingS3<T>
+where
+    T: S3 + Clone + Send + Sync + 'static,
+{
+    async fn get_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        range: Option<String>,
+    ) -> Result<GetObjectResult, SdkError<GetObjectError>> {
+        if self.max_object_size == 0 {
+            return self.inner.get_object(bucket, key, range).await;
+        }
+
+        let flight = {
+            let flight_key = (bucket.to_string(), key.to_string(), range.clone());
+            let mut in_flight = self.in_flight.lock().unwrap();
+            match in_flight.get(&flight_key) {
+                Some(flight) => flight.clone(),
+                None => {
+                    let flight = self.start(flight_key.clone());
+                    in_flight.insert(flight_key, flight.clone());
+                    flight
+                }
+            }
+        };
+
+        let outcome = flight.await;
+        let result = match outcome.as_deref() {
+            Some(Outcome::Object {
+                body,
+                content_range,
+                metadata,
+            }) => Some(Ok(GetObjectResult::new(
+                body.subscribe(),
+                content_range.clone(),
+                metadata.clone(),
+            ))),
+            Some(Outcome::Error(error, status)) => Some(Err(error.to_sdk_error(*status))),
+            Some(Outcome::Exclusive(result)) => result.lock().unwrap().take(),
+            None => None,
+        };
+
+        match result {
+            Some(result) => result,
+            None => self.inner.get_object(bucket, key, range).await,
+        }
+    }
+
+    async fn head_object(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> Result<ObjectMetadata, SdkError<HeadObjectError>> {
+        self.inner.head_object(bucket, key).await
+    }
+
+    async fn head_bucket(
+        &self,
+        bucket: &str,
+        expected_bucket_owner: &str,
+    ) -> Result<(), SdkError<HeadBucketError>> {
+        self.inner.head_bucket(bucket, expected_bucket_owner).await
+    }
+
+    async fn list_objects(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        continuation_token: Option<String>,
+    ) -> Result<ObjectListing, SdkError<ListObjectsV2Error>> {
+        self.inner
+            .list_objects(bucket, prefix, continuation_token)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::s3::tests::{Counting, Objects};
+    use futures_util::future::join_all;
+    use std::time::Duration;
+
+    const BODY: &str = "hello world";
+
+    /// Serves `objects` after a delay, so concurrent reads overlap.
+    fn slow(objects: Objects) -> Counting {
+        Counting::new(objects).with_delay(Duration::from_millis(20))
+    }
+
+    /// Holds [`BODY`] under `key`.
+    fn objects() -> Objects {
+        Objects::default().with("key", BODY, ObjectMetadata::default())
+    }
+
+    #[tokio::test]
+    async fn test_coalesce() {
+        let slow = slow(objects());
+        let coalescing = CoalescingS3::new(slow.clone(), 1024);
+
+        let results = join_all((0..10).map(|_| coalescing.get_object("bucket", "key", None))).await;
+        for result in results {
+            let body = result.unwrap().body().collect().await.unwrap().into_bytes();
+            assert_eq!(body, BODY);
+        }
+        assert_eq!(slow.calls.get_object(), 1);
+        assert!(coalescing.in_flight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_coalesce_distinct_ranges() {
+        let slow = slow(objects());
+        let coalescing = CoalescingS3::new(slow.clone(), 1024);
+
+        let results = join_all(
+            [None, Some("bytes=0-1".to_string())]
+                .map(|range| coalescing.get_object("bucket", "key", range)),
+        )
+        .await;
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(slow.calls.get_object(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_coalesce_error() {
+        let slow = slow(Objects::default());
+        let coalescing = CoalescingS3::new(slow.clone(), 1024);
+
+        let results = join_all((0..3).map(|_| coalescing.get_object("bucket", "key", None))).await;
+        for result in results {
+            let e = result.unwrap_err();
+            assert_eq!(e.raw_response().unwrap().status().as_u16(), 404);
+            assert!(e.into_service_error().is_no_such_key());
+        }
+        assert_eq!(slow.calls.get_object(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_coalesce_too_large() {
+        let slow = slow(objects());
+        let coalescing = CoalescingS3::new(slow.clone(), 5);
+
+        let results = join_all((0..3).map(|_| coalescing.get_object("bucket", "key", None))).await;
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(slow.calls.get_object(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_coalesce_cancelled_waiter() {
+        let slow = slow(objects());
+        let coalescing = CoalescingS3::new(slow.clone(), 1024);
+
+        let first = tokio::spawn({
+            let coalescing = coalescing.clone();
+            async move { coalescing.get_object("bucket", "key", None).await.is_ok() }
+        });
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        first.abort();
+
+        let result = coalescing.get_object("bucket", "key", None).await.unwrap();
+        let body = result.body().collect().await.unwrap().into_bytes();
+        assert_eq!(body, BODY);
+        assert_eq!(slow.calls.get_object(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_shared_body() {
+        let shared = SharedBody::default();
+        let mut early = shared.subscribe();
+
+        // A chunk reaches the waiters before the body is complete.
+        shared.push(Bytes::from_static(b"hello "));
+        assert_eq!(early.try_next().await.unwrap().unwrap(), "hello ");
+
+        let late = shared.subscribe();
+        shared.push(Bytes::from_static(b"world"));
+        shared.finish(Ok(()));
+
+        assert_eq!(early.collect().await.unwrap().into_bytes(), "world");
+        assert_eq!(late.collect().await.unwrap().into_bytes(), BODY);
+        let after = shared.subscribe();
+        assert_eq!(after.collect().await.unwrap().into_bytes(), BODY);
+    }
+
+    #[tokio::test]
+    async fn test_shared_body_error() {
+        let shared = SharedBody::default();
+        let early = shared.subscribe();
+        shared.push(Bytes::from_static(b"hello "));
+        shared.finish(Err("connection reset".to_string()));
+
+        assert!(early.collect().await.is_err());
+        assert!(shared.subscribe().collect().await.is_err());
+    }
+}
@@ -0,0 +1,267 @@
+use crate::encoding::{self, Encoding};
+use crate::response::{self, ResponseBody};
+use async_compression::tokio::bufread::{BrotliEncoder, GzipEncoder, ZstdEncoder};
+use async_compression::Level;
+use futures_util::{StreamExt, TryStreamExt};
+use http_body_util::BodyStream;
+use hyper::body::Incoming;
+use hyper::header::{
+    HeaderValue, ACCEPT_ENCODING, ACCEPT_RANGES, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH,
+    CONTENT_RANGE, CONTENT_TYPE, ETAG, VARY,
+};
+use hyper::service::Service;
+use hyper::{Method, Request, Response, StatusCode};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::io::AsyncRead;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+#[derive(Debug, Clone)]
+pub struct CompressionPolicy {
+    pub encodings: Vec<Encoding>,
+    pub min_size: u64,
+    pub mime_types: Vec<String>,
+}
+
+impl CompressionPolicy {
+    fn is_enabled(&self) -> bool {
+        !self.encodings.is_empty()
+    }
+
+    fn is_compressible<B>(&self, resp: &Response<B>) -> bool {
+        if resp.status() != StatusCode::OK {
+            return false;
+        }
+
+        let headers = resp.headers();
+        if headers.contains_key(CONTENT_ENCODING) || headers.contains_key(CONTENT_RANGE) {
+            return false;
+        }
+        let no_transform = headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|directive| directive.trim().eq_ignore_ascii_case("no-transform"));
+        if no_transform {
+            return false;
+        }
+
+        let content_length = headers
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok());
+        if content_length.is_some_and(|length| length < self.min_size) {
+            return false;
+        }
+
+        headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|content_type| self.is_allowed_mime_type(content_type))
+    }
+
+    fn is_allowed_mime_type(&self, content_type: &str) -> bool {
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+
+        self.mime_types.iter().any(|mime_type| {
+            let mime_type = mime_type.trim().to_ascii_lowercase();
+            match mime_type.strip_suffix("/*") {
+                Some(prefix) => essence.split('/').next() == Some(prefix),
+                None => essence == mime_type,
+            }
+        })
+    }
+}
+
+/// Compresses eligible responses of the inner service according to the client `Accept-Encoding`.
+#[derive(Debug, Clone)]
+pub struct CompressionService<S> {
+    inner: S,
+    policy: Arc<CompressionPolicy>,
+}
+
+impl<S> CompressionService<S> {
+    pub fn new(inner: S, policy: CompressionPolicy) -> Self {
+        Self {
+            inner,
+            policy: Arc::new(policy),
+        }
+    }
+}
+
+impl<S> Service<Request<Incoming>> for CompressionService<S>
+where
+    S: Service<Request<Incoming>, Response = Response<ResponseBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<ResponseBody>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn call(&self, req: Request<Incoming>) -> Self::Future {
+        let policy = self.policy.clone();
+        let encoding = if policy.is_enabled() {
+            let accept_encoding = req
+                .headers()
+                .get(ACCEPT_ENCODING)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default();
+            encoding::negotiate(accept_encoding, &policy.encodings)
+                .first()
+                .copied()
+        } else {
+            None
+        };
+        let is_head = req.method() == Method::HEAD;
+        let future = self.inner.call(req);
+
+        Box::pin(async move {
+            let resp = future.await?;
+            Ok(compress_response(resp, &policy, encoding, is_head))
+        })
+    }
+}
+
+fn compress_response(
+    resp: Response<ResponseBody>,
+    policy: &CompressionPolicy,
+    encoding: Option<Encoding>,
+    is_head: bool,
+) -> Response<ResponseBody> {
+    if !policy.is_enabled() || !policy.is_compressible(&resp) {
+        return resp;
+    }
+
+    let (mut parts, body) = resp.into_parts();
+    let varies = parts
+        .headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|value| value.trim().eq_ignore_ascii_case("accept-encoding"));
+    if !varies {
+        parts
+            .headers
+            .append(VARY, HeaderValue::from_static("Accept-Encoding"));
+    }
+
+    let Some(encoding) = encoding else {
+        return Response::from_parts(parts, body);
+    };
+
+    parts.headers.remove(CONTENT_LENGTH);
+    parts.headers.remove(ACCEPT_RANGES);
+    parts.headers.insert(
+        CONTENT_ENCODING,
+        HeaderValue::from_static(encoding.as_str()),
+    );
+    // The compressed representation is not byte-identical to the stored object.
+    if let Some(e_tag) = parts.headers.get(ETAG).and_then(|v| v.to_str().ok()) {
+        if !e_tag.starts_with("W/") {
+            if let Ok(weak) = HeaderValue::from_str(&format!("W/{}", e_tag)) {
+                parts.headers.insert(ETAG, weak);
+            }
+        }
+    }
+
+    if is_head {
+        return Response::from_parts(parts, body);
+    }
+    Response::from_parts(parts, compress_body(body, encoding))
+}
+
+fn compress_body(body: ResponseBody, encoding: Encoding) -> ResponseBody {
+    let stream = BodyStream::new(body)
+        .try_filter_map(|frame| async move { Ok(frame.into_data().ok()) })
+        .map_err(io::Error::other);
+    let reader = StreamReader::new(Box::pin(stream));
+
+    let encoder: Pin<Box<dyn AsyncRead + Send>> = match encoding {
+        Encoding::Brotli => Box::pin(BrotliEncoder::with_quality(reader, Level::Precise(4))),
+        Encoding::Gzip => Box::pin(GzipEncoder::new(reader)),
+        Encoding::Zstd => Box::pin(ZstdEncoder::new(reader)),
+    };
+
+    response::stream_body(ReaderStream::new(encoder).map(|chunk| chunk.map_err(crate::Error::from)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_compression::tokio::bufread::GzipDecoder;
+    use http_body_util::BodyExt;
+    use test_case::test_case;
+    use tokio::io::AsyncReadExt;
+
+    fn policy() -> CompressionPolicy {
+        CompressionPolicy {
+            encodings: vec![Encoding::Gzip],
+            min_size: 10,
+            mime_types: vec!["text/*".to_string(), "application/json".to_string()],
+        }
+    }
+
+    fn response(headers: &[(&'static str, &'static str)]) -> Response<ResponseBody> {
+        let mut builder = Response::builder().status(StatusCode::OK);
+        for (key, value) in headers {
+            builder = builder.header(*key, *value);
+        }
+        builder
+            .body(response::full_body("hello hello hello hello"))
+            .unwrap()
+    }
+
+    #[test_case(&[("Content-Type", "text/html; charset=utf-8")], true; "wildcard mime type")]
+    #[test_case(&[("Content-Type", "application/json")], true; "exact mime type")]
+    #[test_case(&[("Content-Type", "image/png")], false; "not allowed mime type")]
+    #[test_case(&[], false; "no content type")]
+    #[test_case(&[("Content-Type", "text/css"), ("Content-Length", "100")], true; "large enough")]
+    #[test_case(&[("Content-Type", "text/css"), ("Content-Length", "5")], false; "too small")]
+    #[test_case(&[("Content-Type", "text/css"), ("Content-Encoding", "br")], false; "already encoded")]
+    #[test_case(&[("Content-Type", "text/css"), ("Content-Range", "bytes 0-1/2")], false; "partial content")]
+    #[test_case(&[("Content-Type", "text/css"), ("Cache-Control", "public, no-transform")], false; "no transform")]
+    fn test_is_compressible(headers: &[(&'static str, &'static str)], expected: bool) {
+        assert_eq!(policy().is_compressible(&response(headers)), expected);
+    }
+
+    #[tokio::test]
+    async fn test_compress_response() {
+        let resp = response(&[
+            ("Content-Type", "text/plain"),
+            ("Content-Length", "23"),
+            ("ETag", "\"abc\""),
+        ]);
+        let resp = compress_response(resp, &policy(), Some(Encoding::Gzip), false);
+
+        assert_eq!(resp.headers()[CONTENT_ENCODING], "gzip");
+        assert_eq!(resp.headers()[VARY], "Accept-Encoding");
+        assert_eq!(resp.headers()[ETAG], "W/\"abc\"");
+        assert!(resp.headers().get(CONTENT_LENGTH).is_none());
+
+        let compressed = resp.into_body().collect().await.unwrap().to_bytes();
+        let mut decompressed = String::new();
+        GzipDecoder::new(compressed.as_ref())
+            .read_to_string(&mut decompressed)
+            .await
+            .unwrap();
+        assert_eq!(decompressed, "hello hello hello hello");
+    }
+
+    #[test]
+    fn test_compress_response_identity() {
+        let resp = response(&[("Content-Type", "text/plain")]);
+        let resp = compress_response(resp, &policy(), None, false);
+
+        assert!(resp.headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(resp.headers()[VARY], "Accept-Encoding");
+    }
+}
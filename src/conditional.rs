@@ -0,0 +1,71 @@
+use aws_smithy_types::date_time::Format;
+use aws_smithy_types::DateTime;
+use hyper::header::{IF_MODIFIED_SINCE, IF_NONE_MATCH};
+use hyper::HeaderMap;
+
+/// Evaluates `If-None-Match` and `If-Modified-Since` against the validators of the object.
+/// `If-Modified-Since` is ignored when `If-None-Match` is present.
+pub fn is_not_modified(
+    headers: &HeaderMap,
+    e_tag: Option<&str>,
+    last_modified: Option<&DateTime>,
+) -> bool {
+    if let Some(if_none_match) = headers.get(IF_NONE_MATCH) {
+        let if_none_match = if_none_match.to_str().unwrap_or_default();
+        return match e_tag {
+            Some(e_tag) => if_none_match_matches(if_none_match, e_tag),
+            None => if_none_match.trim() == "*",
+        };
+    }
+
+    let if_modified_since = headers
+        .get(IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| DateTime::from_str(value, Format::HttpDate).ok());
+    match (if_modified_since, last_modified) {
+        (Some(date), Some(last_modified)) => last_modified.secs() <= date.secs(),
+        _ => false,
+    }
+}
+
+/// Compares entity tags using the weak comparison.
+fn if_none_match_matches(if_none_match: &str, e_tag: &str) -> bool {
+    let e_tag = e_tag.trim_start_matches("W/");
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == e_tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    const E_TAG: &str = "\"abc\"";
+    const LAST_MODIFIED: i64 = 1445412480;
+
+    #[test_case(&[("If-None-Match", "\"abc\"")], true; "same etag")]
+    #[test_case(&[("If-None-Match", "W/\"abc\"")], true; "weak etag")]
+    #[test_case(&[("If-None-Match", "\"xyz\", \"abc\"")], true; "etag list")]
+    #[test_case(&[("If-None-Match", "*")], true; "wildcard")]
+    #[test_case(&[("If-None-Match", "\"xyz\"")], false; "different etag")]
+    #[test_case(&[("If-Modified-Since", "Wed, 21 Oct 2015 07:28:00 GMT")], true; "same date")]
+    #[test_case(&[("If-Modified-Since", "Thu, 22 Oct 2015 07:28:00 GMT")], true; "later date")]
+    #[test_case(&[("If-Modified-Since", "Tue, 20 Oct 2015 07:28:00 GMT")], false; "earlier date")]
+    #[test_case(&[("If-Modified-Since", "not a date")], false; "invalid date")]
+    #[test_case(&[("If-None-Match", "\"xyz\""), ("If-Modified-Since", "Thu, 22 Oct 2015 07:28:00 GMT")], false; "etag takes precedence")]
+    #[test_case(&[], false; "unconditional")]
+    fn test_is_not_modified(headers: &[(&'static str, &'static str)], expected: bool) {
+        let mut header_map = HeaderMap::new();
+        for (key, value) in headers {
+            header_map.insert(*key, value.parse().unwrap());
+        }
+        let last_modified = DateTime::from_secs(LAST_MODIFIED);
+
+        assert_eq!(
+            is_not_modified(&header_map, Some(E_TAG), Some(&last_modified)),
+            expected
+        );
+    }
+}
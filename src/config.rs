@@ -1,9 +1,30 @@
-use config::{Config, Environment};
-use serde::Deserialize;
+use crate::encoding::Encoding;
+use crate::error_document::{self, ErrorDocuments};
+use crate::host_mapping::{self, HostMapping, HostMappings};
+use crate::routing_rules::{self, RoutingRules};
+use crate::site::{self, HostSettings, SiteSettings, Sites};
+use crate::tls::{CertificateConfig, TlsVersion};
+use config::{Config, Environment, File};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Environment variable with the path of an optional configuration file.
+const CONFIG_FILE_ENV: &str = "GW_CONFIG_FILE";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("failed to load config: {0}")]
+    Load(#[from] config::ConfigError),
+}
 
 #[derive(Debug, Deserialize)]
 pub struct AppConfig {
+    #[serde(default)]
     pub allow_domains: Vec<String>,
+    #[serde(default, deserialize_with = "host_mapping::deserialize_mappings")]
+    pub host_mappings: Vec<HostMapping>,
     pub root_object: Option<String>,
     pub subdir_root_object: Option<String>,
     pub no_such_key_redirect_object: Option<String>,
@@ -13,6 +34,73 @@ pub struct AppConfig {
     pub gateway_port: u16,
     #[serde(default = "default_management_port")]
     pub management_port: u16,
+    #[serde(default)]
+    pub content_type_source: ContentTypeSource,
+    #[serde(default)]
+    pub forward_metadata: Vec<String>,
+    #[serde(default)]
+    pub precompressed_encodings: Vec<Encoding>,
+    #[serde(default)]
+    pub compression_encodings: Vec<Encoding>,
+    #[serde(default = "default_compression_min_size")]
+    pub compression_min_size: u64,
+    #[serde(default = "default_compression_mime_types")]
+    pub compression_mime_types: Vec<String>,
+    #[serde(default)]
+    pub cache_capacity: u64,
+    #[serde(default = "default_cache_max_object_size")]
+    pub cache_max_object_size: u64,
+    #[serde(default = "default_cache_ttl")]
+    pub cache_ttl: u64,
+    pub disk_cache_dir: Option<PathBuf>,
+    #[serde(default = "default_disk_cache_capacity")]
+    pub disk_cache_capacity: u64,
+    #[serde(default = "default_coalesce_max_object_size")]
+    pub coalesce_max_object_size: u64,
+    #[serde(default = "default_ownership_cache_ttl")]
+    pub ownership_cache_ttl: u64,
+    #[serde(default = "default_ownership_cache_negative_ttl")]
+    pub ownership_cache_negative_ttl: u64,
+    #[serde(default = "default_ownership_cache_max_entries")]
+    pub ownership_cache_max_entries: u64,
+    #[serde(default = "default_negative_cache_ttl")]
+    pub negative_cache_ttl: u64,
+    #[serde(default = "default_negative_cache_max_entries")]
+    pub negative_cache_max_entries: u64,
+    #[serde(default)]
+    pub autoindex_domains: Vec<String>,
+    #[serde(default, deserialize_with = "routing_rules::deserialize")]
+    pub routing_rules: RoutingRules,
+    #[serde(default, deserialize_with = "error_document::deserialize_documents")]
+    pub error_documents: HashMap<u16, String>,
+    #[serde(default)]
+    pub error_document_nearest_404: bool,
+    pub spa_fallback_object: Option<String>,
+    #[serde(default, deserialize_with = "site::deserialize")]
+    pub host_settings: Vec<HostSettings>,
+    #[serde(default = "default_config_watch_interval")]
+    pub config_watch_interval: u64,
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+    pub http2_max_concurrent_streams: Option<u32>,
+    pub http2_initial_stream_window_size: Option<u32>,
+    pub http2_initial_connection_window_size: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_nested")]
+    pub tls_certificates: Vec<CertificateConfig>,
+    #[serde(default)]
+    pub tls_min_version: TlsVersion,
+    #[serde(default = "default_tls_watch_interval")]
+    pub tls_watch_interval: u64,
+    pub http_redirect_port: Option<u16>,
+}
+
+/// Which Content-Type wins when both the object metadata and the key extension provide one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentTypeSource {
+    #[default]
+    Extension,
+    Object,
 }
 
 fn default_gateway_port() -> u16 {
@@ -23,19 +111,298 @@ fn default_management_port() -> u16 {
     8080
 }
 
+fn default_compression_min_size() -> u64 {
+    1024
+}
+
+fn default_compression_mime_types() -> Vec<String> {
+    vec![
+        "text/html".to_string(),
+        "text/css".to_string(),
+        "text/plain".to_string(),
+        "text/javascript".to_string(),
+        "application/javascript".to_string(),
+        "application/json".to_string(),
+        "application/xml".to_string(),
+        "image/svg+xml".to_string(),
+    ]
+}
+
+fn default_cache_max_object_size() -> u64 {
+    1024 * 1024
+}
+
+fn default_cache_ttl() -> u64 {
+    60
+}
+
+fn default_disk_cache_capacity() -> u64 {
+    1024 * 1024 * 1024
+}
+
+fn default_coalesce_max_object_size() -> u64 {
+    8 * 1024 * 1024
+}
+
+fn default_ownership_cache_ttl() -> u64 {
+    300
+}
+
+fn default_ownership_cache_negative_ttl() -> u64 {
+    30
+}
+
+fn default_ownership_cache_max_entries() -> u64 {
+    10000
+}
+
+fn default_negative_cache_ttl() -> u64 {
+    10
+}
+
+fn default_negative_cache_max_entries() -> u64 {
+    10000
+}
+
+fn default_config_watch_interval() -> u64 {
+    10
+}
+
+fn default_shutdown_timeout() -> u64 {
+    30
+}
+
+fn default_tls_watch_interval() -> u64 {
+    60
+}
+
 impl AppConfig {
-    pub fn new() -> Self {
-        Config::builder()
+    /// Loads the configuration file given by `GW_CONFIG_FILE`, if any, with the `GW_*`
+    /// environment variables layered on top.
+    pub fn new() -> Result<Self, ConfigError> {
+        Self::load(config_file().as_deref(), Environment::with_prefix("GW"))
+    }
+
+    /// Builds the routing state of the gateway, which is replaced on reload.
+    pub fn sites(&self) -> Sites {
+        Sites::new(
+            HostMappings::new(self.host_mappings.clone(), &self.allow_domains),
+            SiteSettings {
+                root_object: self.root_object.clone(),
+                subdir_root_object: self.subdir_root_object.clone(),
+                no_such_key_redirect_object: self.no_such_key_redirect_object.clone(),
+                spa_fallback_object: self.spa_fallback_object.clone(),
+                autoindex: None,
+                allow_cross_account: self.allow_cross_account,
+                error_documents: ErrorDocuments::new(
+                    self.error_documents.clone(),
+                    self.error_document_nearest_404,
+                ),
+            },
+            self.host_settings.clone(),
+            self.autoindex_domains.clone(),
+            self.routing_rules.clone(),
+        )
+    }
+
+    fn load(path: Option<&Path>, environment: Environment) -> Result<Self, ConfigError> {
+        let mut builder = Config::builder();
+        if let Some(path) = path {
+            // The format is detected from the extension: `.toml`, `.yaml` or `.yml`.
+            builder = builder.add_source(File::from(path).required(true));
+        }
+
+        Ok(builder
             .add_source(
-                Environment::with_prefix("GW")
+                environment
                     .prefix_separator("_")
                     .list_separator(",")
                     .with_list_parse_key("allow_domains")
+                    .with_list_parse_key("host_mappings")
+                    .with_list_parse_key("forward_metadata")
+                    .with_list_parse_key("precompressed_encodings")
+                    .with_list_parse_key("compression_encodings")
+                    .with_list_parse_key("compression_mime_types")
+                    .with_list_parse_key("autoindex_domains")
+                    .with_list_parse_key("error_documents")
                     .try_parsing(true),
             )
-            .build()
-            .expect("Failed to build config")
-            .try_deserialize()
-            .expect("Failed to deserialize config")
+            .build()?
+            .try_deserialize()?)
+    }
+}
+
+pub fn config_file() -> Option<PathBuf> {
+    std::env::var_os(CONFIG_FILE_ENV).map(PathBuf::from)
+}
+
+/// Deserializes a nested value given either as a JSON string, as environment variables can only
+/// carry strings, or as structured data in a configuration file.
+pub fn deserialize_nested<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::String(json) => serde_json::from_str(&json),
+        value => serde_json::from_value(value),
+    }
+    .map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::StatusCode;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+    use test_case::test_case;
+
+    fn file(suffix: &str, content: &str) -> NamedTempFile {
+        let mut file = tempfile::Builder::new().suffix(suffix).tempfile().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        file
+    }
+
+    fn load(path: Option<&Path>, vars: &[(&str, &str)]) -> Result<AppConfig, ConfigError> {
+        let vars = vars
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        AppConfig::load(path, Environment::with_prefix("GW").source(Some(vars)))
+    }
+
+    const TOML: &str = r#"
+allow_domains = ["*.example.com"]
+gateway_port = 9000
+compression_encodings = ["br", "gzip"]
+error_documents = ["404=404.html"]
+
+[[host_settings]]
+host = "docs.example.com"
+spa_fallback_object = "index.html"
+
+[[routing_rules]]
+Host = "www.example.com"
+
+[[routing_rules.RoutingRules]]
+Condition = { KeyPrefixEquals = "docs/" }
+Redirect = { ReplaceKeyPrefixWith = "documents/", HttpRedirectCode = "302" }
+"#;
+
+    const YAML: &str = r#"
+allow_domains:
+  - "*.example.com"
+gateway_port: 9000
+compression_encodings: [br, gzip]
+error_documents: ["404=404.html"]
+host_settings:
+  - host: docs.example.com
+    spa_fallback_object: index.html
+routing_rules:
+  - Host: www.example.com
+    RoutingRules:
+      - Condition:
+          KeyPrefixEquals: docs/
+        Redirect:
+          ReplaceKeyPrefixWith: documents/
+          HttpRedirectCode: "302"
+"#;
+
+    #[test]
+    fn test_defaults() {
+        let config = load(None, &[]).unwrap();
+        assert!(config.allow_domains.is_empty());
+        assert_eq!(config.gateway_port, 8000);
+        assert_eq!(config.management_port, 8080);
+    }
+
+    #[test_case(".toml", TOML; "toml")]
+    #[test_case(".yaml", YAML; "yaml")]
+    fn test_file(suffix: &str, content: &str) {
+        let file = file(suffix, content);
+        let config = load(Some(file.path()), &[]).unwrap();
+
+        assert_eq!(config.allow_domains, vec!["*.example.com"]);
+        assert_eq!(config.gateway_port, 9000);
+        assert_eq!(
+            config.compression_encodings,
+            vec![Encoding::Brotli, Encoding::Gzip]
+        );
+        assert_eq!(
+            config.error_documents,
+            HashMap::from([(404, "404.html".to_string())])
+        );
+
+        let redirect = routing_rules::evaluate(
+            config.routing_rules.for_host("www.example.com"),
+            "www.example.com",
+            "docs/a.html",
+            None,
+        )
+        .unwrap();
+        assert_eq!(redirect.status, StatusCode::FOUND);
+        assert_eq!(redirect.location, "/documents/a.html");
+
+        let sites = site::Sites::new(
+            host_mapping::HostMappings::new(Vec::new(), &config.allow_domains),
+            site::SiteSettings::default(),
+            config.host_settings,
+            Vec::new(),
+            config.routing_rules,
+        );
+        assert_eq!(
+            sites
+                .resolve("docs.example.com")
+                .unwrap()
+                .settings()
+                .spa_fallback_object
+                .as_deref(),
+            Some("index.html")
+        );
+    }
+
+    #[test]
+    fn test_env_overrides_file() {
+        let file = file(".toml", TOML);
+        let config = load(
+            Some(file.path()),
+            &[
+                ("GW_GATEWAY_PORT", "9001"),
+                ("GW_ALLOW_DOMAINS", "a.example.net,b.example.net"),
+                (
+                    "GW_ROUTING_RULES",
+                    r#"[{"Host": "*.example.org", "RoutingRules": [{"Redirect": {"ReplaceKeyWith": "a"}}]}]"#,
+                ),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(config.gateway_port, 9001);
+        assert_eq!(config.allow_domains, vec!["a.example.net", "b.example.net"]);
+        assert_eq!(
+            config.compression_encodings,
+            vec![Encoding::Brotli, Encoding::Gzip]
+        );
+        assert!(config.routing_rules.for_host("www.example.com").is_empty());
+        assert_eq!(config.routing_rules.for_host("www.example.org").len(), 1);
+    }
+
+    #[test_case(".toml", "gateway_port = \"http\""; "invalid value")]
+    #[test_case(".toml", "gateway_port = "; "invalid syntax")]
+    #[test_case(".yaml", "routing_rules: [{Host: a.example.com, RoutingRules: [{}]}]"; "invalid routing rules")]
+    #[test_case(".yaml", "host_settings: [{host: a.example.com, index: a.html}]"; "unknown host setting")]
+    #[test_case(".ini", "gateway_port = 9000"; "unsupported format")]
+    fn test_file_invalid(suffix: &str, content: &str) {
+        let file = file(suffix, content);
+        assert!(load(Some(file.path()), &[]).is_err());
+    }
+
+    #[test]
+    fn test_file_missing() {
+        let err = load(Some(Path::new("/nonexistent/gateway.toml")), &[])
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("/nonexistent/gateway.toml"));
     }
 }
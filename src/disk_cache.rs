@@ -0,0 +1,795 @@
+use crate::cache;
+use crate::range;
+use crate::s3::{GetObjectResult, ObjectListing, ObjectMetadata, S3};
+use aws_sdk_s3::error::SdkError;
+use aws_sdk_s3::operation::get_object::GetObjectError;
+use aws_sdk_s3::operation::head_bucket::HeadBucketError;
+use aws_sdk_s3::operation::head_object::HeadObjectError;
+use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Error;
+use aws_smithy_types::body::SdkBody;
+use aws_smithy_types::byte_stream::{ByteStream, Length};
+use bytes::Bytes;
+use hyper::body::{Body, Frame, SizeHint};
+use moka::future::Cache;
+use moka::policy::EvictionPolicy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+
+const BODY_EXTENSION: &str = "body";
+const META_EXTENSION: &str = "meta";
+const TEMP_EXTENSION: &str = "tmp";
+/// Chunks of a body buffered for the cache writer before the copy is abandoned.
+const COPY_BUFFER_CHUNKS: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct DiskCacheConfig {
+    pub dir: PathBuf,
+    /// Total size of cached object bodies in bytes.
+    pub capacity: u64,
+    /// See [`CacheConfig::ttl`](crate::cache::CacheConfig::ttl).
+    pub ttl: Duration,
+}
+
+/// The metadata file stored next to each cached body.
+#[derive(Debug, Serialize, Deserialize)]
+struct DiskEntry {
+    bucket: String,
+    key: String,
+    /// Distinguishes the files of successive fetches of the same object.
+    generation: u64,
+    /// Seconds since the Unix epoch. Revalidation updates it in place.
+    fetched_at: AtomicU64,
+    ttl: u64,
+    size: u64,
+    metadata: ObjectMetadata,
+}
+
+impl DiskEntry {
+    fn is_fresh(&self) -> bool {
+        now().saturating_sub(self.fetched_at.load(Ordering::Relaxed)) < self.ttl
+    }
+
+    fn file_name(&self, extension: &str) -> String {
+        format!(
+            "{}.{:016x}.{}",
+            file_stem(&self.bucket, &self.key),
+            self.generation,
+            extension
+        )
+    }
+}
+
+#[derive(Debug)]
+struct DiskStore {
+    dir: PathBuf,
+    capacity: u64,
+    ttl: Duration,
+    index: Cache<(String, String), Arc<DiskEntry>>,
+    /// Source of generations and temporary file names, unique across restarts.
+    counter: AtomicU64,
+}
+
+impl DiskStore {
+    async fn open(config: DiskCacheConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.dir).await?;
+
+        let dir = config.dir.clone();
+        let index = Cache::builder()
+            .max_capacity(config.capacity)
+            .eviction_policy(EvictionPolicy::lru())
+            .weigher(|_: &(String, String), entry: &Arc<DiskEntry>| {
+                entry.size.try_into().unwrap_or(u32::MAX)
+            })
+            .eviction_listener(move |_, entry: Arc<DiskEntry>, _| {
+                // Every generation has its own files, so this never removes the files of the
+                // entry that replaced it.
+                let paths =
+                    [META_EXTENSION, BODY_EXTENSION].map(|ext| dir.join(entry.file_name(ext)));
+                match Handle::try_current() {
+                    Ok(handle) => drop(handle.spawn_blocking(move || remove_files(&paths))),
+                    Err(_) => remove_files(&paths),
+                }
+            })
+            .build();
+
+        let store = Self {
+            dir: config.dir,
+            capacity: config.capacity,
+            ttl: config.ttl,
+            index,
+            counter: AtomicU64::new(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_nanos() as u64)
+                    .unwrap_or_default(),
+            ),
+        };
+        store.rebuild_index().await?;
+        Ok(store)
+    }
+
+    /// Loads the entries left by a previous process, oldest first so that recency is roughly preserved.
+    /// Files not named like cache files are left alone, as the directory may be shared.
+    async fn rebuild_index(&self) -> io::Result<()> {
+        let mut entries = Vec::new();
+        let mut dir = fs::read_dir(&self.dir).await?;
+        while let Some(file) = dir.next_entry().await? {
+            let path = file.path();
+            if !path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(is_cache_file_name)
+            {
+                continue;
+            }
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some(META_EXTENSION) => match self.load_entry(&path).await {
+                    Some(entry) => entries.push(entry),
+                    None => remove_entry_files(&path).await,
+                },
+                Some(BODY_EXTENSION) => {
+                    if fs::metadata(path.with_extension(META_EXTENSION))
+                        .await
+                        .is_err()
+                    {
+                        remove_entry_files(&path).await;
+                    }
+                }
+                _ => {
+                    if let Err(e) = fs::remove_file(&path).await {
+                        tracing::warn!("failed to remove stray cache file: {:?}", e);
+                    }
+                }
+            }
+        }
+
+        // An older generation of the same object is replaced, which removes its files.
+        entries.sort_by_key(|entry| entry.fetched_at.load(Ordering::Relaxed));
+        let count = entries.len();
+        for entry in entries {
+            self.index
+                .insert((entry.bucket.clone(), entry.key.clone()), Arc::new(entry))
+                .await;
+        }
+        self.index.run_pending_tasks().await;
+        tracing::info!(
+            "disk cache loaded {} entries from {}",
+            count,
+            self.dir.display()
+        );
+
+        Ok(())
+    }
+
+    async fn load_entry(&self, meta_path: &Path) -> Option<DiskEntry> {
+        let entry: DiskEntry = serde_json::from_slice(&fs::read(meta_path).await.ok()?).ok()?;
+        let body = fs::metadata(meta_path.with_extension(BODY_EXTENSION))
+            .await
+            .ok()?;
+        // The bucket and key stored in the file must be the ones its name was derived from.
+        let expected = self.path(&entry, META_EXTENSION);
+        (body.len() == entry.size && expected == meta_path).then_some(entry)
+    }
+
+    fn path(&self, entry: &DiskEntry, extension: &str) -> PathBuf {
+        self.dir.join(entry.file_name(extension))
+    }
+
+    fn temp_path(&self, entry: &DiskEntry) -> PathBuf {
+        let n = self.counter.fetch_add(1, Ordering::Relaxed);
+        self.dir.join(format!(
+            "{}.{:016x}.{}",
+            file_stem(&entry.bucket, &entry.key),
+            n,
+            TEMP_EXTENSION
+        ))
+    }
+
+    fn new_entry(
+        &self,
+        bucket: &str,
+        key: &str,
+        ttl: Duration,
+        metadata: ObjectMetadata,
+    ) -> DiskEntry {
+        DiskEntry {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            generation: self.counter.fetch_add(1, Ordering::Relaxed),
+            fetched_at: AtomicU64::new(now()),
+            ttl: ttl.as_secs(),
+            size: metadata.content_length().unwrap_or_default().max(0) as u64,
+            metadata,
+        }
+    }
+
+    async fn get(&self, bucket: &str, key: &str) -> Option<Arc<DiskEntry>> {
+        self.index.get(&(bucket.to_string(), key.to_string())).await
+    }
+
+    async fn invalidate(&self, bucket: &str, key: &str) {
+        self.index
+            .invalidate(&(bucket.to_string(), key.to_string()))
+            .await;
+    }
+
+    async fn write_meta(&self, entry: &DiskEntry) -> io::Result<()> {
+        let temp = self.temp_path(entry);
+        fs::write(&temp, serde_json::to_vec(entry)?).await?;
+        fs::rename(&temp, self.path(entry, META_EXTENSION)).await
+    }
+
+    async fn insert(&self, entry: DiskEntry) {
+        if let Err(e) = self.write_meta(&entry).await {
+            tracing::warn!("failed to write cache metadata: {:?}", e);
+            return;
+        }
+        self.index
+            .insert((entry.bucket.clone(), entry.key.clone()), Arc::new(entry))
+            .await;
+    }
+
+    /// Marks the entry as fresh again without replacing it in the index.
+    async fn revalidate(&self, entry: &DiskEntry) {
+        entry.fetched_at.store(now(), Ordering::Relaxed);
+        if let Err(e) = self.write_meta(entry).await {
+            tracing::warn!("failed to write cache metadata: {:?}", e);
+        }
+    }
+
+    /// Writes the chunks of a body to a temporary file and moves it into place once the whole
+    /// body has arrived. The file is dropped when the body ends early, e.g. because the client
+    /// went away.
+    async fn write_copy(self: Arc<Self>, entry: DiskEntry, mut chunks: mpsc::Receiver<Bytes>) {
+        let temp = self.temp_path(&entry);
+        let result = async {
+            let mut file = File::create(&temp).await?;
+            let mut size = 0;
+            while let Some(chunk) = chunks.recv().await {
+                file.write_all(&chunk).await?;
+                size += chunk.len() as u64;
+            }
+            if size != entry.size {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("expected {} bytes, got {}", entry.size, size),
+                ));
+            }
+            file.sync_all().await?;
+            fs::rename(&temp, self.path(&entry, BODY_EXTENSION)).await
+        }
+        .await;
+
+        match result {
+            Ok(()) => self.insert(entry).await,
+            Err(e) => {
+                if e.kind() == io::ErrorKind::UnexpectedEof {
+                    tracing::debug!(
+                        "discarded incomplete cache body: s3://{}/{}: {}",
+                        entry.bucket,
+                        entry.key,
+                        e
+                    );
+                } else {
+                    tracing::warn!(
+                        "failed to write cache body: s3://{}/{}: {:?}",
+                        entry.bucket,
+                        entry.key,
+                        e
+                    );
+                }
+                let _ = fs::remove_file(&temp).await;
+            }
+        }
+    }
+
+    /// Streams the cached body from its file. Returns `None` if the file has gone away
+    /// or the range cannot be served from a single slice.
+    async fn read(&self, entry: &DiskEntry, byte_range: Option<&str>) -> Option<GetObjectResult> {
+        let file = File::open(self.path(entry, BODY_EXTENSION)).await.ok()?;
+
+        let Some(byte_range) = byte_range else {
+            let body = ByteStream::read_from().file(file).build().await.ok()?;
+            return Some(GetObjectResult::new(body, None, entry.metadata.clone()));
+        };
+
+        let (start, end) = range::resolve_single(byte_range, entry.size)?;
+        let length = end - start + 1;
+        let body = ByteStream::read_from()
+            .file(file)
+            .offset(start)
+            .length(Length::Exact(length))
+            .build()
+            .await
+            .ok()?;
+        let metadata = entry.metadata.clone().with_content_length(length as i64);
+
+        Some(GetObjectResult::new(
+            body,
+            Some(range::content_range(start, end, entry.size)),
+            metadata,
+        ))
+    }
+}
+
+/// Passes a body through to the client while handing a copy of every chunk to the cache writer.
+/// The copy is abandoned when the writer falls behind, so a slow disk never holds back the client.
+struct TeeBody {
+    inner: SdkBody,
+    copy: Option<mpsc::Sender<Bytes>>,
+}
+
+impl Body for TeeBody {
+    type Data = Bytes;
+    type Error = aws_smithy_types::body::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        let frame = ready!(Pin::new(&mut self.inner).poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    if self
+                        .copy
+                        .as_ref()
+                        .is_some_and(|copy| copy.try_send(data.clone()).is_err())
+                    {
+                        self.copy = None;
+                    }
+                }
+            }
+            // The writer keeps the copy only if it received the whole body.
+            _ => self.copy = None,
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// An [`S3`] implementation that keeps object bodies in a directory so that they survive restarts.
+/// Entries are evicted in least recently used order once the byte budget is exceeded.
+#[derive(Debug, Clone)]
+pub struct DiskCachedS3<T> {
+    inner: T,
+    store: Option<Arc<DiskStore>>,
+}
+
+impl<T> DiskCachedS3<T> {
+    pub async fn new(inner: T, config: Option<DiskCacheConfig>) -> io::Result<Self> {
+        let store = match config {
+            Some(config) => Some(Arc::new(DiskStore::open(config).await?)),
+            None => None,
+        };
+
+        Ok(Self { inner, store })
+    }
+}
+
+impl<T> DiskCachedS3<T>
+where
+    T: S3 + Send + Sync,
+{
+    async fn lookup(&self, store: &DiskStore, bucket: &str, key: &str) -> Option<Arc<DiskEntry>> {
+        let entry = store.get(bucket, key).await?;
+        if entry.is_fresh() {
+            return Some(entry);
+        }
+
+        match self.inner.head_object(bucket, key).await {
+            Ok(head) if head.e_tag().is_some() && head.e_tag() == entry.metadata.e_tag() => {
+                store.revalidate(&entry).await;
+                Some(entry)
+            }
+            _ => {
+                store.invalidate(bucket, key).await;
+                None
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<T> S3 for DiskCachedS3<T>
+where
+    T: S3 + Send + Sync,
+{
+    async fn get_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        range: Option<String>,
+    ) -> Result<GetObjectResult, SdkError<GetObjectError>> {
+        let Some(store) = &self.store else {
+            return self.inner.get_object(bucket, key, range).await;
+        };
+
+        if let Some(entry) = self.lookup(store, bucket, key).await {
+            if let Some(result) = store.read(&entry, range.as_deref()).await {
+                tracing::debug!("disk cache hit: s3://{}/{}", bucket, key);
+                return Ok(result);
+            }
+        }
+        if range.is_some() {
+            return self.inner.get_object(bucket, key, range).await;
+        }
+
+        let resp = self.inner.get_object(bucket, key, None).await?;
+        let cacheable = resp
+            .metadata()
+            .content_length()
+            .is_some_and(|length| length >= 0 && length as u64 <= store.capacity);
+        let Some(ttl) = cache::freshness_lifetime(resp.metadata(), store.ttl).filter(|_| cacheable)
+        else {
+            return Ok(resp);
+        };
+
+        // The client gets the body as it arrives while a copy is written in the background.
+        let entry = store.new_entry(bucket, key, ttl, resp.metadata().clone());
+        let metadata = entry.metadata.clone();
+        let (copy, chunks) = mpsc::channel(COPY_BUFFER_CHUNKS);
+        tokio::spawn(store.clone().write_copy(entry, chunks));
+
+        let body = TeeBody {
+            inner: resp.body().into_inner(),
+            copy: Some(copy),
+        };
+        Ok(GetObjectResult::new(
+            ByteStream::new(SdkBody::from_body_1_x(body)),
+            None,
+            metadata,
+        ))
+    }
+
+    async fn head_object(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> Result<ObjectMetadata, SdkError<HeadObjectError>> {
+        if let Some(store) = &self.store {
+            if let Some(entry) = store.get(bucket, key).await.filter(|e| e.is_fresh()) {
+                return Ok(entry.metadata.clone());
+            }
+        }
+
+        self.inner.head_object(bucket, key).await
+    }
+
+    async fn head_bucket(
+        &self,
+        bucket: &str,
+        expected_bucket_owner: &str,
+    ) -> Result<(), SdkError<HeadBucketError>> {
+        self.inner.head_bucket(bucket, expected_bucket_owner).await
+    }
+
+    async fn list_objects(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        continuation_token: Option<String>,
+    ) -> Result<ObjectListing, SdkError<ListObjectsV2Error>> {
+        self.inner
+            .list_objects(bucket, prefix, continuation_token)
+            .await
+    }
+}
+
+/// Names the files of an entry after the SHA-256 digest of its bucket and key, which stays the
+/// same across releases. Bucket names cannot contain `/`, so the input is unambiguous.
+fn file_stem(bucket: &str, key: &str) -> String {
+    Sha256::digest(format!("{}/{}", bucket, key))
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Whether `name` is `<file stem>.<16 hex digit generation>.<extension>`, as written by the cache.
+fn is_cache_file_name(name: &str) -> bool {
+    let is_hex = |part: &str, len: usize| {
+        part.len() == len && part.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    };
+    match name.split('.').collect::<Vec<_>>()[..] {
+        [stem, generation, extension] => {
+            is_hex(stem, 64)
+                && is_hex(generation, 16)
+                && [META_EXTENSION, BODY_EXTENSION, TEMP_EXTENSION].contains(&extension)
+        }
+        _ => false,
+    }
+}
+
+fn remove_files(paths: &[PathBuf]) {
+    for path in paths {
+        if let Err(e) = std::fs::remove_file(path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                tracing::warn!("failed to remove cache file: {:?}", e);
+            }
+        }
+    }
+}
+
+async fn remove_entry_files(path: &Path) {
+    for extension in [META_EXTENSION, BODY_EXTENSION] {
+        let _ = fs::remove_file(path.with_extension(extension)).await;
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::tests::{fake, objects, BODY};
+    use crate::s3::tests::Counting;
+    use bytes::Bytes;
+    use test_case::test_case;
+
+    fn config(dir: &Path, capacity: u64) -> Option<DiskCacheConfig> {
+        Some(DiskCacheConfig {
+            dir: dir.to_path_buf(),
+            capacity,
+            ttl: Duration::from_secs(60),
+        })
+    }
+
+    async fn body(result: GetObjectResult) -> Bytes {
+        result.body().collect().await.unwrap().into_bytes()
+    }
+
+    /// Reads the object and waits for its copy, which is written in the background.
+    async fn fetch(cached: &DiskCachedS3<Counting>, key: &str) -> Bytes {
+        let result = cached.get_object("bucket", key, None).await.unwrap();
+        let body = body(result).await;
+        let store = cached.store.as_ref().unwrap();
+        for _ in 0..100 {
+            if store.get("bucket", key).await.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        body
+    }
+
+    #[tokio::test]
+    async fn test_disk_cache_hit() {
+        let dir = tempfile::tempdir().unwrap();
+        let fake = fake("\"abc\"");
+        let cached = DiskCachedS3::new(fake.clone(), config(dir.path(), 1024))
+            .await
+            .unwrap();
+
+        for _ in 0..3 {
+            assert_eq!(fetch(&cached, "key").await, BODY);
+        }
+        assert_eq!(fake.calls.get_object(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_disk_cache_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let fake = fake("\"abc\"");
+        let cached = DiskCachedS3::new(fake.clone(), config(dir.path(), 1024))
+            .await
+            .unwrap();
+        fetch(&cached, "key").await;
+
+        let result = cached
+            .get_object("bucket", "key", Some("bytes=0-4".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(result.content_range(), Some("bytes 0-4/11"));
+        assert_eq!(result.metadata().content_length(), Some(5));
+        assert_eq!(body(result).await, "hello");
+        assert_eq!(fake.calls.get_object(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_disk_cache_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let fake = fake("\"abc\"");
+        let cached = DiskCachedS3::new(fake.clone(), config(dir.path(), 1024))
+            .await
+            .unwrap();
+        fetch(&cached, "key").await;
+        drop(cached);
+
+        let cached = DiskCachedS3::new(fake.clone(), config(dir.path(), 1024))
+            .await
+            .unwrap();
+        let result = cached.get_object("bucket", "key", None).await.unwrap();
+        assert_eq!(result.metadata().e_tag(), Some("\"abc\""));
+        assert_eq!(body(result).await, BODY);
+        assert_eq!(fake.calls.get_object(), 1);
+    }
+
+    /// Waits for the files of evicted entries, which are removed in the background.
+    async fn body_files(dir: &Path, expected: usize) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        for _ in 0..100 {
+            files = std::fs::read_dir(dir)
+                .unwrap()
+                .map(|file| file.unwrap().path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == BODY_EXTENSION))
+                .collect();
+            if files.len() == expected {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        files
+    }
+
+    #[tokio::test]
+    async fn test_disk_cache_eviction() {
+        let dir = tempfile::tempdir().unwrap();
+        let fake = fake("\"abc\"");
+        let cached = DiskCachedS3::new(fake.clone(), config(dir.path(), 2 * BODY.len() as u64))
+            .await
+            .unwrap();
+
+        for key in ["a", "b", "c"] {
+            fetch(&cached, key).await;
+        }
+        let store = cached.store.as_ref().unwrap();
+        store.index.run_pending_tasks().await;
+
+        assert_eq!(store.index.entry_count(), 2);
+        assert!(store.get("bucket", "a").await.is_none());
+        let files = body_files(dir.path(), 2).await;
+        assert_eq!(files.len(), 2);
+        let c = store.get("bucket", "c").await.unwrap();
+        assert!(files.contains(&store.path(&c, BODY_EXTENSION)));
+    }
+
+    #[tokio::test]
+    async fn test_disk_cache_changed_object() {
+        let dir = tempfile::tempdir().unwrap();
+        let fake = fake("\"abc\"");
+        let cached = DiskCachedS3::new(
+            fake.clone(),
+            Some(DiskCacheConfig {
+                dir: dir.path().to_path_buf(),
+                capacity: 1024,
+                ttl: Duration::ZERO,
+            }),
+        )
+        .await
+        .unwrap();
+        fetch(&cached, "key").await;
+
+        // The stale entry is invalidated and replaced by a new generation, whose files must
+        // survive the removal of the old ones.
+        let cached = DiskCachedS3 {
+            inner: Counting {
+                objects: objects("\"xyz\"", None),
+                ..fake.clone()
+            },
+            ..cached
+        };
+        fetch(&cached, "key").await;
+        let store = cached.store.as_ref().unwrap();
+        store.index.run_pending_tasks().await;
+
+        let entry = store.get("bucket", "key").await.unwrap();
+        assert_eq!(entry.metadata.e_tag(), Some("\"xyz\""));
+        assert_eq!(
+            body_files(dir.path(), 1).await,
+            vec![store.path(&entry, BODY_EXTENSION)]
+        );
+        let result = cached.get_object("bucket", "key", None).await.unwrap();
+        assert_eq!(body(result).await, BODY);
+        assert_eq!(fake.calls.get_object(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_disk_cache_incomplete_body() {
+        let dir = tempfile::tempdir().unwrap();
+        let fake = fake("\"abc\"");
+        let cached = DiskCachedS3::new(fake.clone(), config(dir.path(), 1024))
+            .await
+            .unwrap();
+
+        // The client goes away before reading the body.
+        drop(cached.get_object("bucket", "key", None).await.unwrap());
+        for _ in 0..100 {
+            if std::fs::read_dir(dir.path()).unwrap().count() == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        fetch(&cached, "key").await;
+        fetch(&cached, "key").await;
+        assert_eq!(fake.calls.get_object(), 2);
+    }
+
+    #[test]
+    fn test_file_stem() {
+        assert_eq!(
+            file_stem("bucket", "key"),
+            "0a9d370e050857030986e38bdd6cc1bf687fe4941aca697da0e8920d8ec408e3"
+        );
+    }
+
+    #[test_case("0a9d370e050857030986e38bdd6cc1bf687fe4941aca697da0e8920d8ec408e3.0000000000000001.body", true; "body")]
+    #[test_case("0a9d370e050857030986e38bdd6cc1bf687fe4941aca697da0e8920d8ec408e3.0000000000000001.meta", true; "meta")]
+    #[test_case("0a9d370e050857030986e38bdd6cc1bf687fe4941aca697da0e8920d8ec408e3.0000000000000001.tmp", true; "temp")]
+    #[test_case("0a9d370e050857030986e38bdd6cc1bf687fe4941aca697da0e8920d8ec408e3.0000000000000001.txt", false; "other extension")]
+    #[test_case("0a9d370e050857030986e38bdd6cc1bf687fe4941aca697da0e8920d8ec408e3.1.body", false; "short generation")]
+    #[test_case("0123.0000000000000001.body", false; "short stem")]
+    #[test_case("notes.body", false; "foreign")]
+    fn test_is_cache_file_name(name: &str, expected: bool) {
+        assert_eq!(is_cache_file_name(name), expected);
+    }
+
+    #[tokio::test]
+    async fn test_disk_cache_rebuild_removes_stray_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let stem = file_stem("bucket", "key");
+        std::fs::write(
+            dir.path().join(format!("{}.0000000000000001.body", stem)),
+            BODY,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join(format!("{}.0000000000000002.tmp", stem)),
+            BODY,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join(format!("{}.0000000000000003.meta", stem)),
+            "{",
+        )
+        .unwrap();
+
+        DiskCachedS3::new(fake("\"abc\""), config(dir.path(), 1024))
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_disk_cache_rebuild_keeps_foreign_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let foreign = ["notes.txt", "0123.body", "backup.meta", "0123.1.tmp"];
+        for name in foreign {
+            std::fs::write(dir.path().join(name), BODY).unwrap();
+        }
+
+        DiskCachedS3::new(fake("\"abc\""), config(dir.path(), 1024))
+            .await
+            .unwrap();
+        for name in foreign {
+            assert_eq!(
+                std::fs::read(dir.path().join(name)).unwrap(),
+                BODY.as_bytes()
+            );
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use serde::Deserialize;
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Encoding {
+    #[serde(rename = "br")]
+    Brotli,
+    #[serde(rename = "gzip")]
+    Gzip,
+    #[serde(rename = "zstd")]
+    Zstd,
+}
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Zstd => "zstd",
+        }
+    }
+
+    /// The key suffix of a precompressed variant, e.g. `app.js.br`.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => ".br",
+            Encoding::Gzip => ".gz",
+            Encoding::Zstd => ".zst",
+        }
+    }
+}
+
+/// Parses an `Accept-Encoding` header value into codings and their q-values.
+pub fn parse_accept_encoding(value: &str) -> Vec<(String, f32)> {
+    value
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';').map(str::trim);
+            let coding = params.next().filter(|coding| !coding.is_empty())?;
+            let q = params
+                .find_map(|param| {
+                    param
+                        .strip_prefix("q=")
+                        .or_else(|| param.strip_prefix("Q="))
+                })
+                .map(|q| q.parse::<f32>().unwrap_or(0.0))
+                .unwrap_or(1.0);
+            Some((coding.to_ascii_lowercase(), q))
+        })
+        .collect()
+}
+
+/// Returns the supported encodings acceptable to the client, most preferred first.
+/// Ties in q-value are broken by the order of `supported`.
+pub fn negotiate(accept_encoding: &str, supported: &[Encoding]) -> Vec<Encoding> {
+    let accepted = parse_accept_encoding(accept_encoding);
+    let wildcard = accepted
+        .iter()
+        .find(|(coding, _)| coding == "*")
+        .map(|(_, q)| *q);
+
+    let mut candidates = supported
+        .iter()
+        .filter_map(|encoding| {
+            let q = accepted
+                .iter()
+                .find(|(coding, _)| {
+                    coding == encoding.as_str()
+                        || (*encoding == Encoding::Gzip && coding == "x-gzip")
+                })
+                .map(|(_, q)| *q)
+                .or(wildcard)?;
+            (q > 0.0).then_some((*encoding, q))
+        })
+        .collect::<Vec<(Encoding, f32)>>();
+    candidates.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+
+    candidates
+        .into_iter()
+        .map(|(encoding, _)| encoding)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    const SUPPORTED: &[Encoding] = &[Encoding::Brotli, Encoding::Gzip, Encoding::Zstd];
+
+    #[test_case("gzip, br", vec![Encoding::Brotli, Encoding::Gzip]; "server order on tie")]
+    #[test_case("gzip;q=1.0, br;q=0.5", vec![Encoding::Gzip, Encoding::Brotli]; "q-values")]
+    #[test_case("br;q=0, gzip", vec![Encoding::Gzip]; "refused coding")]
+    #[test_case("x-gzip", vec![Encoding::Gzip]; "x-gzip alias")]
+    #[test_case("*", vec![Encoding::Brotli, Encoding::Gzip, Encoding::Zstd]; "wildcard")]
+    #[test_case("zstd, *;q=0", vec![Encoding::Zstd]; "wildcard refused")]
+    #[test_case("identity", vec![]; "identity only")]
+    #[test_case("", vec![]; "empty")]
+    fn test_negotiate(accept_encoding: &str, expected: Vec<Encoding>) {
+        assert_eq!(negotiate(accept_encoding, SUPPORTED), expected);
+    }
+
+    #[test]
+    fn test_negotiate_unsupported() {
+        assert_eq!(
+            negotiate("br, gzip", &[Encoding::Gzip]),
+            vec![Encoding::Gzip]
+        );
+    }
+}
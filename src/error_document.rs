@@ -0,0 +1,155 @@
+use hyper::StatusCode;
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const STATUS_CODES: [u16; 4] = [403, 404, 500, 503];
+const DEFAULT_NOT_FOUND_DOCUMENT: &str = "404.html";
+/// Prefixes of the requested key searched for the nearest 404 document, nearest first, so that
+/// a deeply nested path does not turn into as many S3 requests.
+const MAX_NEAREST_DEPTH: usize = 8;
+
+/// Objects served inline as the body of error responses, keeping the status code.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorDocuments {
+    documents: Arc<HashMap<u16, String>>,
+    /// Looks up the 404 document in every prefix of the requested key, nearest first.
+    nearest_not_found: bool,
+}
+
+impl ErrorDocuments {
+    pub fn new(documents: HashMap<u16, String>, nearest_not_found: bool) -> Self {
+        Self {
+            documents: Arc::new(documents),
+            nearest_not_found,
+        }
+    }
+
+    /// Returns a copy with the documents and the nearest lookup replaced where given.
+    pub fn with_overrides(
+        &self,
+        documents: Option<HashMap<u16, String>>,
+        nearest_not_found: Option<bool>,
+    ) -> Self {
+        Self {
+            documents: documents.map_or_else(|| self.documents.clone(), Arc::new),
+            nearest_not_found: nearest_not_found.unwrap_or(self.nearest_not_found),
+        }
+    }
+
+    /// Returns the keys to try, in order, for an error response with `status` to `key`.
+    pub fn keys(&self, status: StatusCode, key: &str) -> Vec<String> {
+        let document = self.documents.get(&status.as_u16());
+        if status != StatusCode::NOT_FOUND || !self.nearest_not_found {
+            return document.cloned().into_iter().collect();
+        }
+
+        let document = document.map_or(DEFAULT_NOT_FOUND_DOCUMENT, String::as_str);
+        let name = document.rsplit('/').next().unwrap_or(document);
+        let mut keys = key
+            .match_indices('/')
+            .rev()
+            .take(MAX_NEAREST_DEPTH)
+            .map(|(i, _)| format!("{}{}", &key[..=i], name))
+            .chain([name.to_string(), document.to_string()])
+            .collect::<Vec<String>>();
+        keys.dedup();
+        keys
+    }
+}
+
+/// Deserializes `status=key` pairs, e.g. `404=errors/404.html`.
+pub fn deserialize_documents<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<u16, String>, D::Error> {
+    let pairs = Vec::<String>::deserialize(deserializer)?;
+    parse_documents(&pairs).map_err(serde::de::Error::custom)
+}
+
+pub fn parse_documents(pairs: &[String]) -> Result<HashMap<u16, String>, String> {
+    pairs.iter().map(|pair| parse_document(pair)).collect()
+}
+
+fn parse_document(pair: &str) -> Result<(u16, String), String> {
+    let (status, key) = pair
+        .split_once('=')
+        .ok_or_else(|| format!("expected status=key: {}", pair))?;
+    let status = status
+        .trim()
+        .parse::<u16>()
+        .map_err(|e| format!("invalid status code: {}: {}", status, e))?;
+    if !STATUS_CODES.contains(&status) {
+        return Err(format!(
+            "unsupported status code for an error document: {}",
+            status
+        ));
+    }
+    let key = key.trim().trim_start_matches('/');
+    if key.is_empty() {
+        return Err(format!("empty error document key: {}", pair));
+    }
+
+    Ok((status, key.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn documents(nearest_not_found: bool) -> ErrorDocuments {
+        ErrorDocuments::new(
+            HashMap::from([
+                (404, "errors/404.html".to_string()),
+                (500, "errors/500.html".to_string()),
+            ]),
+            nearest_not_found,
+        )
+    }
+
+    #[test_case(StatusCode::NOT_FOUND, "a/b/c.html", vec!["errors/404.html"]; "not found")]
+    #[test_case(StatusCode::INTERNAL_SERVER_ERROR, "a/b/c.html", vec!["errors/500.html"]; "internal server error")]
+    #[test_case(StatusCode::FORBIDDEN, "a/b/c.html", vec![]; "no document")]
+    fn test_keys(status: StatusCode, key: &str, expected: Vec<&str>) {
+        assert_eq!(documents(false).keys(status, key), expected);
+    }
+
+    #[test_case("a/b/c.html", vec!["a/b/404.html", "a/404.html", "404.html", "errors/404.html"]; "nested key")]
+    #[test_case("a/b/", vec!["a/b/404.html", "a/404.html", "404.html", "errors/404.html"]; "prefix")]
+    #[test_case("c.html", vec!["404.html", "errors/404.html"]; "top level key")]
+    fn test_keys_nearest(key: &str, expected: Vec<&str>) {
+        assert_eq!(documents(true).keys(StatusCode::NOT_FOUND, key), expected);
+        assert_eq!(
+            documents(true).keys(StatusCode::INTERNAL_SERVER_ERROR, key),
+            vec!["errors/500.html"]
+        );
+    }
+
+    #[test]
+    fn test_keys_nearest_depth() {
+        let key = format!("{}x.html", "a/".repeat(1000));
+        let keys = documents(true).keys(StatusCode::NOT_FOUND, &key);
+
+        assert_eq!(keys.len(), MAX_NEAREST_DEPTH + 2);
+        assert_eq!(keys[0], format!("{}404.html", "a/".repeat(1000)));
+        assert_eq!(keys[MAX_NEAREST_DEPTH..], ["404.html", "errors/404.html"]);
+    }
+
+    #[test]
+    fn test_keys_nearest_default_document() {
+        let documents = ErrorDocuments::new(HashMap::new(), true);
+        assert_eq!(
+            documents.keys(StatusCode::NOT_FOUND, "a/c.html"),
+            vec!["a/404.html", "404.html"]
+        );
+    }
+
+    #[test_case("404=404.html", Ok((404, "404.html".to_string())); "valid")]
+    #[test_case("503 = /errors/503.html", Ok((503, "errors/503.html".to_string())); "trimmed")]
+    #[test_case("404.html", Err("expected status=key: 404.html".to_string()); "missing status")]
+    #[test_case("200=ok.html", Err("unsupported status code for an error document: 200".to_string()); "unsupported status")]
+    #[test_case("404=", Err("empty error document key: 404=".to_string()); "empty key")]
+    fn test_parse_document(pair: &str, expected: Result<(u16, String), String>) {
+        assert_eq!(parse_document(pair), expected);
+    }
+}
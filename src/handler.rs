@@ -1,9 +1,13 @@
-use crate::response;
-use crate::response::ResponseError;
-use crate::s3::S3;
-use bytes::Bytes;
-use http_body_util::Full;
-use hyper::{Response, StatusCode};
+use crate::encoding::Encoding;
+use crate::ownership::OwnershipCache;
+use crate::response::{MetadataPolicy, ResponseBody, ResponseError};
+use crate::s3::{ObjectMetadata, S3};
+use crate::site::Site;
+use crate::{conditional, encoding, listing, range, response};
+use hyper::header::{
+    HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, IF_RANGE, RANGE, VARY,
+};
+use hyper::{HeaderMap, Response, StatusCode};
 
 #[derive(Debug, thiserror::Error)]
 pub enum HandlerError {
@@ -11,32 +15,153 @@ pub enum HandlerError {
     Response(#[from] ResponseError),
 }
 
+/// The gateway state and the request details shared by the handlers.
+#[derive(Debug)]
+pub struct RequestContext<'a, T> {
+    pub s3_client: &'a T,
+    pub ownership_cache: &'a OwnershipCache,
+    pub metadata_policy: &'a MetadataPolicy,
+    pub precompressed_encodings: &'a [Encoding],
+    /// The account that must own the bucket, `None` when cross-account access is allowed.
+    pub self_account_id: Option<&'a str>,
+    pub headers: &'a HeaderMap,
+    /// Whether the request is a HEAD request, answered without a body.
+    pub head: bool,
+    /// Whether a missing key may be answered with the SPA fallback object of the site.
+    pub spa_route: bool,
+}
+
+impl<T> RequestContext<'_, T> {
+    fn spa_fallback_object<'s>(&self, site: &'s Site) -> Option<&'s str> {
+        site.settings()
+            .spa_fallback_object
+            .as_deref()
+            .filter(|_| self.spa_route)
+    }
+}
+
 pub async fn s3_handle<T>(
-    s3_client: &T,
-    no_such_key_redirect_object: Option<String>,
-    self_account_id: Option<String>,
-    bucket: &str,
+    ctx: &RequestContext<'_, T>,
+    site: &Site,
     key: &str,
-) -> Result<Response<Full<Bytes>>, HandlerError>
+) -> Result<Response<ResponseBody>, HandlerError>
 where
-    T: S3 + Send + Sync + 'static,
+    T: S3 + Clone + Send + Sync + 'static,
 {
-    tracing::info!("get object: s3://{}/{}", bucket, key);
+    let origin = site.origin();
+    tracing::info!("get object: s3://{}/{}", origin.bucket(), origin.key(key));
 
-    if let Some(id) = self_account_id {
-        if let Err(e) = s3_client.head_bucket(bucket, &id).await {
-            tracing::warn!(
-                "failed to head bucket: bucket: {} e: {:?}",
+    let resp = s3_get_object_handle(ctx, site, key).await?;
+
+    Ok(with_vary(resp, ctx.precompressed_encodings))
+}
+
+async fn s3_get_object_handle<T>(
+    ctx: &RequestContext<'_, T>,
+    site: &Site,
+    key: &str,
+) -> Result<Response<ResponseBody>, HandlerError>
+where
+    T: S3 + Clone + Send + Sync + 'static,
+{
+    let RequestContext {
+        s3_client,
+        metadata_policy,
+        headers,
+        ..
+    } = *ctx;
+    let origin = site.origin();
+    let bucket = origin.bucket();
+    let key = &origin.key(key);
+
+    if !is_owned_bucket(ctx, bucket).await {
+        return Ok(response::easy_response(StatusCode::FORBIDDEN)?);
+    }
+
+    let ranges = headers
+        .get(RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(range::parse);
+    if ranges.is_none() {
+        if let Some(variant) = precompressed_variant(ctx, bucket, key).await {
+            match s3_client.get_object(bucket, &variant.key, None).await {
+                Ok(resp) => {
+                    if let Some(location) = website_redirect_location(resp.metadata()) {
+                        return Ok(response::redirect_response(
+                            StatusCode::MOVED_PERMANENTLY,
+                            location,
+                        )?);
+                    }
+                    if conditional::is_not_modified(
+                        headers,
+                        resp.metadata().e_tag(),
+                        resp.metadata().last_modified(),
+                    ) {
+                        return Ok(response::not_modified_response(resp.metadata())?);
+                    }
+
+                    let resp = response::s3_ok_response(metadata_policy, key, resp)?;
+                    return Ok(variant.apply(resp, metadata_policy, key));
+                }
+                Err(e) => tracing::warn!(
+                    "failed to get precompressed variant: s3://{}/{}: {:?}",
+                    bucket,
+                    variant.key,
+                    e.into_service_error()
+                ),
+            }
+        }
+    }
+
+    let ranges = ranges.as_deref();
+    let status = match s3_object_handle(ctx, bucket, key, ranges).await? {
+        Ok(resp) => return Ok(resp),
+        Err(status) => status,
+    };
+    if let (StatusCode::NOT_FOUND, Some(fallback)) = (status, ctx.spa_fallback_object(site)) {
+        let fallback = &origin.key(fallback);
+        match s3_object_handle(ctx, bucket, fallback, ranges).await? {
+            Ok(resp) => return Ok(resp),
+            Err(status) => tracing::warn!(
+                "failed to get spa fallback object: bucket: {} key: {} status: {}",
                 bucket,
-                e.into_service_error()
-            );
-            return Ok(response::easy_response(StatusCode::FORBIDDEN)?);
+                fallback,
+                status
+            ),
+        }
+    }
+
+    let no_such_key_redirect_object = site.settings().no_such_key_redirect_object.clone();
+    Ok(response::s3_error_response(s3_client, origin, status, no_such_key_redirect_object).await?)
+}
+
+/// Serves `key` in full or in the requested byte ranges, answering conditional requests.
+/// Returns the status of the gateway response when the object could not be fetched.
+async fn s3_object_handle<T>(
+    ctx: &RequestContext<'_, T>,
+    bucket: &str,
+    key: &str,
+    ranges: Option<&[range::ByteRange]>,
+) -> Result<Result<Response<ResponseBody>, StatusCode>, HandlerError>
+where
+    T: S3 + Clone + Send + Sync + 'static,
+{
+    let RequestContext {
+        s3_client,
+        metadata_policy,
+        headers,
+        ..
+    } = *ctx;
+    if let Some(ranges) = ranges {
+        if let Some(resp) = s3_range_handle(ctx, bucket, key, ranges).await? {
+            return Ok(Ok(resp));
         }
     }
 
-    let resp = match s3_client.get_object(bucket, key).await {
+    let resp = match s3_client.get_object(bucket, key, None).await {
         Ok(resp) => resp,
         Err(e) => {
+            let status = error_status(e.raw_response().map(|raw| raw.status().as_u16()));
             let error = e.into_service_error();
             tracing::warn!(
                 "failed to get object: bucket: {} key: {} e: {:?}",
@@ -44,27 +169,831 @@ where
                 key,
                 error,
             );
-            return Ok(response::s3_error_response(
-                s3_client,
+            if error.is_no_such_key() {
+                return Ok(Err(StatusCode::NOT_FOUND));
+            }
+            return Ok(Err(status));
+        }
+    };
+
+    if let Some(location) = website_redirect_location(resp.metadata()) {
+        return Ok(Ok(response::redirect_response(
+            StatusCode::MOVED_PERMANENTLY,
+            location,
+        )?));
+    }
+    if conditional::is_not_modified(
+        headers,
+        resp.metadata().e_tag(),
+        resp.metadata().last_modified(),
+    ) {
+        return Ok(Ok(response::not_modified_response(resp.metadata())?));
+    }
+
+    Ok(Ok(response::s3_ok_response(metadata_policy, key, resp)?))
+}
+
+pub async fn s3_head_handle<T>(
+    ctx: &RequestContext<'_, T>,
+    site: &Site,
+    key: &str,
+) -> Result<Response<ResponseBody>, HandlerError>
+where
+    T: S3 + Send + Sync + 'static,
+{
+    let origin = site.origin();
+    tracing::info!("head object: s3://{}/{}", origin.bucket(), origin.key(key));
+
+    let resp = s3_head_object_handle(ctx, site, key).await?;
+
+    Ok(with_vary(resp, ctx.precompressed_encodings))
+}
+
+async fn s3_head_object_handle<T>(
+    ctx: &RequestContext<'_, T>,
+    site: &Site,
+    key: &str,
+) -> Result<Response<ResponseBody>, HandlerError>
+where
+    T: S3 + Send + Sync + 'static,
+{
+    let RequestContext {
+        s3_client,
+        metadata_policy,
+        headers,
+        ..
+    } = *ctx;
+    let origin = site.origin();
+    let bucket = origin.bucket();
+    let key = &origin.key(key);
+
+    if !is_owned_bucket(ctx, bucket).await {
+        return Ok(response::easy_response(StatusCode::FORBIDDEN)?);
+    }
+
+    if let Some(variant) = precompressed_variant(ctx, bucket, key).await {
+        if let Ok(head) = s3_client.head_object(bucket, &variant.key).await {
+            if let Some(location) = website_redirect_location(&head) {
+                return Ok(response::redirect_response(
+                    StatusCode::MOVED_PERMANENTLY,
+                    location,
+                )?);
+            }
+            if conditional::is_not_modified(headers, head.e_tag(), head.last_modified()) {
+                return Ok(response::not_modified_response(&head)?);
+            }
+
+            let resp = response::s3_head_response(metadata_policy, key, &head)?;
+            return Ok(variant.apply(resp, metadata_policy, key));
+        }
+    }
+
+    let status = match s3_head_object(ctx, bucket, key).await? {
+        Ok(resp) => return Ok(resp),
+        Err(status) => status,
+    };
+    if let (StatusCode::NOT_FOUND, Some(fallback)) = (status, ctx.spa_fallback_object(site)) {
+        let fallback = &origin.key(fallback);
+        match s3_head_object(ctx, bucket, fallback).await? {
+            Ok(resp) => return Ok(resp),
+            Err(status) => tracing::warn!(
+                "failed to head spa fallback object: bucket: {} key: {} status: {}",
                 bucket,
-                error.is_no_such_key(),
-                no_such_key_redirect_object,
-            )
-            .await?);
+                fallback,
+                status
+            ),
+        }
+    }
+
+    let no_such_key_redirect_object = site.settings().no_such_key_redirect_object.clone();
+    Ok(response::s3_error_response(s3_client, origin, status, no_such_key_redirect_object).await?)
+}
+
+/// Answers a HEAD request for `key`, including conditional requests. Returns the status of
+/// the gateway response when the object could not be found.
+async fn s3_head_object<T>(
+    ctx: &RequestContext<'_, T>,
+    bucket: &str,
+    key: &str,
+) -> Result<Result<Response<ResponseBody>, StatusCode>, HandlerError>
+where
+    T: S3 + Send + Sync + 'static,
+{
+    let RequestContext {
+        s3_client,
+        metadata_policy,
+        headers,
+        ..
+    } = *ctx;
+    let head = match s3_client.head_object(bucket, key).await {
+        Ok(head) => head,
+        Err(e) => {
+            let status = error_status(e.raw_response().map(|raw| raw.status().as_u16()));
+            let error = e.into_service_error();
+            tracing::warn!(
+                "failed to head object: bucket: {} key: {} e: {:?}",
+                bucket,
+                key,
+                error,
+            );
+            if error.is_not_found() {
+                return Ok(Err(StatusCode::NOT_FOUND));
+            }
+            return Ok(Err(status));
         }
     };
 
-    let body = match resp.body().collect().await {
-        Ok(body) => body.into_bytes(),
+    if let Some(location) = website_redirect_location(&head) {
+        return Ok(Ok(response::redirect_response(
+            StatusCode::MOVED_PERMANENTLY,
+            location,
+        )?));
+    }
+    if conditional::is_not_modified(headers, head.e_tag(), head.last_modified()) {
+        return Ok(Ok(response::not_modified_response(&head)?));
+    }
+
+    Ok(Ok(response::s3_head_response(metadata_policy, key, &head)?))
+}
+
+/// Returns the `x-amz-website-redirect-location` of the object when it is a path or an
+/// absolute http(s) URL, as S3 static website hosting accepts.
+fn website_redirect_location(metadata: &ObjectMetadata) -> Option<&str> {
+    let location = metadata.website_redirect_location()?;
+    if location.starts_with('/')
+        || location.starts_with("http://")
+        || location.starts_with("https://")
+    {
+        return Some(location);
+    }
+
+    tracing::warn!("invalid website redirect location: {}", location);
+    None
+}
+
+/// Maps the status S3 responded with to the status of the gateway response. Errors other than
+/// missing keys, denied access and throttling are reported as internal server errors.
+fn error_status(status: Option<u16>) -> StatusCode {
+    match status {
+        Some(403) => StatusCode::FORBIDDEN,
+        Some(404) => StatusCode::NOT_FOUND,
+        Some(503) => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn accepted_encodings(headers: &HeaderMap, precompressed_encodings: &[Encoding]) -> Vec<Encoding> {
+    if precompressed_encodings.is_empty() {
+        return Vec::new();
+    }
+
+    let accept_encoding = headers
+        .get(ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    encoding::negotiate(accept_encoding, precompressed_encodings)
+}
+
+/// A precompressed copy of an object, stored next to it with the extension of its encoding.
+struct Variant {
+    key: String,
+    encoding: Encoding,
+    /// The metadata of the object itself.
+    base: ObjectMetadata,
+}
+
+impl Variant {
+    /// Marks a response built from the variant as encoded and gives it the Content-Type of
+    /// the object itself rather than the one stored with the variant.
+    fn apply(
+        &self,
+        mut resp: Response<ResponseBody>,
+        metadata_policy: &MetadataPolicy,
+        key: &str,
+    ) -> Response<ResponseBody> {
+        resp.headers_mut().insert(
+            CONTENT_ENCODING,
+            HeaderValue::from_static(self.encoding.as_str()),
+        );
+        if let Ok(content_type) =
+            HeaderValue::from_str(&metadata_policy.content_type(key, &self.base))
+        {
+            resp.headers_mut().insert(CONTENT_TYPE, content_type);
+        }
+        resp
+    }
+}
+
+/// Finds the first precompressed variant of `key` that the client accepts. Variants are only
+/// served for objects that exist and do not redirect, and are probed with HeadObject so that only the chosen
+/// object is fetched.
+async fn precompressed_variant<T>(
+    ctx: &RequestContext<'_, T>,
+    bucket: &str,
+    key: &str,
+) -> Option<Variant>
+where
+    T: S3 + Send + Sync + 'static,
+{
+    let s3_client = ctx.s3_client;
+    let encodings = accepted_encodings(ctx.headers, ctx.precompressed_encodings);
+    if encodings.is_empty() {
+        return None;
+    }
+    let base = s3_client.head_object(bucket, key).await.ok()?;
+    // A redirect stub answers with its redirect, whatever its siblings hold.
+    if website_redirect_location(&base).is_some() {
+        return None;
+    }
+
+    for encoding in encodings {
+        let variant_key = format!("{}{}", key, encoding.extension());
+        if s3_client.object_exists(bucket, &variant_key).await {
+            return Some(Variant {
+                key: variant_key,
+                encoding,
+                base,
+            });
+        }
+        tracing::debug!("no precompressed variant: s3://{}/{}", bucket, variant_key);
+    }
+    None
+}
+
+fn with_vary(
+    mut resp: Response<ResponseBody>,
+    precompressed_encodings: &[Encoding],
+) -> Response<ResponseBody> {
+    if !precompressed_encodings.is_empty() {
+        resp.headers_mut()
+            .append(VARY, HeaderValue::from_static("Accept-Encoding"));
+    }
+    resp
+}
+
+/// Renders the objects and common prefixes directly under `prefix`. HEAD requests get the
+/// status and headers of the same response.
+pub async fn s3_list_handle<T>(
+    ctx: &RequestContext<'_, T>,
+    site: &Site,
+    query: Option<&str>,
+    prefix: &str,
+) -> Result<Response<ResponseBody>, HandlerError>
+where
+    T: S3 + Send + Sync + 'static,
+{
+    let resp = s3_list_objects_handle(ctx, site, query, prefix).await?;
+    if ctx.head {
+        return Ok(response::without_body(resp));
+    }
+    Ok(resp)
+}
+
+async fn s3_list_objects_handle<T>(
+    ctx: &RequestContext<'_, T>,
+    site: &Site,
+    query: Option<&str>,
+    prefix: &str,
+) -> Result<Response<ResponseBody>, HandlerError>
+where
+    T: S3 + Send + Sync + 'static,
+{
+    let s3_client = ctx.s3_client;
+    let origin = site.origin();
+    let bucket = origin.bucket();
+    tracing::info!("list objects: s3://{}/{}", bucket, origin.key(prefix));
+
+    if !is_owned_bucket(ctx, bucket).await {
+        return Ok(response::easy_response(StatusCode::FORBIDDEN)?);
+    }
+
+    let continuation_token = listing::continuation_token(query);
+    let listing = match s3_client
+        .list_objects(bucket, &origin.key(prefix), continuation_token)
+        .await
+    {
+        Ok(listing) => listing.strip_prefix(origin.prefix()),
         Err(e) => {
-            tracing::error!("failed to collect body: {:?}", e);
+            tracing::warn!(
+                "failed to list objects: bucket: {} prefix: {} e: {:?}",
+                bucket,
+                origin.key(prefix),
+                e.into_service_error()
+            );
             return Ok(response::easy_response(StatusCode::INTERNAL_SERVER_ERROR)?);
         }
     };
 
-    let content_type = mime_guess::from_path(key)
-        .first_or(mime::TEXT_PLAIN)
-        .to_string();
+    // A prefix without any children does not exist as a directory.
+    if !prefix.is_empty() && listing.objects().is_empty() && listing.common_prefixes().is_empty() {
+        return Ok(response::easy_response(StatusCode::NOT_FOUND)?);
+    }
+
+    Ok(listing::listing_response(
+        prefix,
+        &listing,
+        listing::prefers_json(ctx.headers),
+    )?)
+}
+
+/// Serves the first existing object among `keys` as the body of an error response with `status`,
+/// or only its headers to a HEAD request. Returns `None` when none of them exists.
+pub async fn s3_error_document_handle<T>(
+    ctx: &RequestContext<'_, T>,
+    site: &Site,
+    status: StatusCode,
+    keys: &[String],
+) -> Result<Option<Response<ResponseBody>>, HandlerError>
+where
+    T: S3 + Send + Sync + 'static,
+{
+    let RequestContext {
+        s3_client,
+        metadata_policy,
+        ..
+    } = *ctx;
+    let origin = site.origin();
+    let bucket = origin.bucket();
+    if !is_owned_bucket(ctx, bucket).await {
+        return Ok(None);
+    }
+
+    for key in keys.iter().map(|key| origin.key(key)) {
+        if ctx.head {
+            match s3_client.head_object(bucket, &key).await {
+                Ok(head) => {
+                    tracing::info!("error document: {} s3://{}/{}", status, bucket, key);
+                    return Ok(Some(response::s3_error_document_head_response(
+                        metadata_policy,
+                        status,
+                        &key,
+                        &head,
+                    )?));
+                }
+                Err(e) => tracing::debug!(
+                    "no error document: s3://{}/{}: {:?}",
+                    bucket,
+                    key,
+                    e.into_service_error()
+                ),
+            }
+            continue;
+        }
+
+        match s3_client.get_object(bucket, &key, None).await {
+            Ok(resp) => {
+                tracing::info!("error document: {} s3://{}/{}", status, bucket, key);
+                return Ok(Some(response::s3_error_document_response(
+                    metadata_policy,
+                    status,
+                    &key,
+                    resp,
+                )?));
+            }
+            Err(e) => tracing::debug!(
+                "no error document: s3://{}/{}: {:?}",
+                bucket,
+                key,
+                e.into_service_error()
+            ),
+        }
+    }
+
+    Ok(None)
+}
+
+async fn is_owned_bucket<T>(ctx: &RequestContext<'_, T>, bucket: &str) -> bool
+where
+    T: S3 + Send + Sync + 'static,
+{
+    let Some(id) = ctx.self_account_id else {
+        return true;
+    };
+
+    ctx.ownership_cache
+        .is_owned(ctx.s3_client, id, bucket)
+        .await
+}
+
+/// Serves the requested byte ranges. Returns `None` when the full representation must be
+/// served instead, e.g. when `If-Range` does not match or the object could not be fetched.
+async fn s3_range_handle<T>(
+    ctx: &RequestContext<'_, T>,
+    bucket: &str,
+    key: &str,
+    ranges: &[range::ByteRange],
+) -> Result<Option<Response<ResponseBody>>, HandlerError>
+where
+    T: S3 + Clone + Send + Sync + 'static,
+{
+    let RequestContext {
+        s3_client,
+        metadata_policy,
+        headers,
+        ..
+    } = *ctx;
+    if ranges.len() > range::MAX_RANGES {
+        return Ok(None);
+    }
+    let if_range = headers
+        .get(IF_RANGE)
+        .map(|value| value.to_str().unwrap_or_default());
+
+    if let [byte_range] = *ranges {
+        let resp = match s3_client
+            .get_object(bucket, key, Some(byte_range.to_string()))
+            .await
+        {
+            Ok(resp) => resp,
+            Err(e) if e.raw_response().map(|raw| raw.status().as_u16()) == Some(416) => {
+                let total = s3_client
+                    .head_object(bucket, key)
+                    .await
+                    .ok()
+                    .and_then(|head| head.content_length());
+                return Ok(Some(response::range_not_satisfiable_response(total)?));
+            }
+            Err(_) => return Ok(None),
+        };
+
+        if let Some(location) = website_redirect_location(resp.metadata()) {
+            return Ok(Some(response::redirect_response(
+                StatusCode::MOVED_PERMANENTLY,
+                location,
+            )?));
+        }
+        if conditional::is_not_modified(
+            headers,
+            resp.metadata().e_tag(),
+            resp.metadata().last_modified(),
+        ) {
+            return Ok(Some(response::not_modified_response(resp.metadata())?));
+        }
+        if let Some(if_range) = if_range {
+            if !range::if_range_matches(
+                if_range,
+                resp.metadata().e_tag(),
+                resp.metadata().last_modified(),
+            ) {
+                return Ok(None);
+            }
+        }
+
+        return match resp.content_range().map(str::to_string) {
+            Some(content_range) => Ok(Some(response::s3_partial_response(
+                metadata_policy,
+                key,
+                &content_range,
+                resp,
+            )?)),
+            None => Ok(Some(response::s3_ok_response(metadata_policy, key, resp)?)),
+        };
+    }
+
+    let head = match s3_client.head_object(bucket, key).await {
+        Ok(head) => head,
+        Err(_) => return Ok(None),
+    };
+    if let Some(location) = website_redirect_location(&head) {
+        return Ok(Some(response::redirect_response(
+            StatusCode::MOVED_PERMANENTLY,
+            location,
+        )?));
+    }
+    if conditional::is_not_modified(headers, head.e_tag(), head.last_modified()) {
+        return Ok(Some(response::not_modified_response(&head)?));
+    }
+    if let Some(if_range) = if_range {
+        if !range::if_range_matches(if_range, head.e_tag(), head.last_modified()) {
+            return Ok(None);
+        }
+    }
+
+    let total = head.content_length().unwrap_or_default().max(0) as u64;
+    let resolved = ranges
+        .iter()
+        .filter_map(|byte_range| byte_range.resolve(total))
+        .collect::<Vec<(u64, u64)>>();
 
-    Ok(response::s3_ok_response(content_type, body)?)
+    match resolved[..] {
+        [] => Ok(Some(response::range_not_satisfiable_response(
+            head.content_length(),
+        )?)),
+        [(start, end)] => {
+            let resp = match s3_client
+                .get_object(
+                    bucket,
+                    key,
+                    Some(range::ByteRange::Bounded(start, end).to_string()),
+                )
+                .await
+            {
+                Ok(resp) => resp,
+                Err(_) => return Ok(None),
+            };
+            Ok(Some(response::s3_partial_response(
+                metadata_policy,
+                key,
+                &range::content_range(start, end, total),
+                resp,
+            )?))
+        }
+        _ => Ok(Some(response::s3_multipart_byteranges_response(
+            s3_client.clone(),
+            metadata_policy,
+            bucket,
+            key,
+            &head,
+            resolved,
+        )?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ContentTypeSource;
+    use crate::host_mapping::HostMappings;
+    use crate::ownership::OwnershipCacheConfig;
+    use crate::router;
+    use crate::routing_rules::RoutingRules;
+    use crate::s3::tests::Objects;
+    use crate::site::{SiteSettings, Sites};
+    use bytes::Bytes;
+    use http_body_util::BodyExt;
+    use hyper::header::{CONTENT_LENGTH, LOCATION};
+    use std::time::Duration;
+    use test_case::test_case;
+
+    fn objects() -> Objects {
+        let metadata =
+            |content_type: &str| ObjectMetadata::builder().content_type(content_type).build();
+        Objects::default()
+            .with("style.css", "body {}", metadata("text/css"))
+            .with("style.css.gz", "gzipped", metadata("application/gzip"))
+            .with("orphan.css.gz", "gzipped", metadata("application/gzip"))
+            .with(
+                "old.css",
+                "",
+                ObjectMetadata::builder()
+                    .website_redirect_location("/style.css")
+                    .build(),
+            )
+            .with("old.css.gz", "gzipped", metadata("application/gzip"))
+            .with(
+                "index.html",
+                "<html>",
+                ObjectMetadata::builder()
+                    .content_type("text/html")
+                    .e_tag("\"shell\"")
+                    .build(),
+            )
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), HeaderValue::from_static(value)))
+            .collect()
+    }
+
+    fn site(settings: SiteSettings) -> Site {
+        Sites::new(
+            HostMappings::new(Vec::new(), &["*.example.com".to_string()]),
+            settings,
+            Vec::new(),
+            Vec::new(),
+            RoutingRules::default(),
+        )
+        .resolve("bucket.example.com")
+        .unwrap()
+    }
+
+    fn ownership_cache() -> OwnershipCache {
+        OwnershipCache::new(OwnershipCacheConfig {
+            positive_ttl: Duration::from_secs(300),
+            negative_ttl: Duration::from_secs(30),
+            max_entries: 100,
+        })
+    }
+
+    fn context<'a>(
+        objects: &'a Objects,
+        ownership_cache: &'a OwnershipCache,
+        metadata_policy: &'a MetadataPolicy,
+        headers: &'a HeaderMap,
+        head: bool,
+    ) -> RequestContext<'a, Objects> {
+        RequestContext {
+            s3_client: objects,
+            ownership_cache,
+            metadata_policy,
+            precompressed_encodings: &[Encoding::Gzip],
+            self_account_id: None,
+            headers,
+            head,
+            spa_route: false,
+        }
+    }
+
+    async fn get(
+        objects: &Objects,
+        metadata_policy: &MetadataPolicy,
+        spa_fallback_object: Option<&str>,
+        headers: &HeaderMap,
+        key: &str,
+    ) -> Response<ResponseBody> {
+        let ownership_cache = ownership_cache();
+        let site = site(SiteSettings {
+            spa_fallback_object: spa_fallback_object.map(str::to_string),
+            ..Default::default()
+        });
+        let ctx = RequestContext {
+            spa_route: router::is_spa_route(headers, &format!("/{}", key)),
+            ..context(objects, &ownership_cache, metadata_policy, headers, false)
+        };
+        s3_handle(&ctx, &site, key).await.unwrap()
+    }
+
+    /// Sends the request as GET and as HEAD, and checks that both get the same status and
+    /// headers. Returns the GET response.
+    async fn get_and_head<F, Fut>(request: F) -> Response<ResponseBody>
+    where
+        F: Fn(bool) -> Fut,
+        Fut: std::future::Future<Output = Response<ResponseBody>>,
+    {
+        let (get, head) = (request(false).await, request(true).await);
+        assert_eq!(head.status(), get.status());
+        assert_eq!(head.headers()[CONTENT_TYPE], get.headers()[CONTENT_TYPE]);
+
+        let (parts, body) = get.into_parts();
+        let body = body.collect().await.unwrap().to_bytes();
+        assert_eq!(head.headers()[CONTENT_LENGTH], body.len().to_string());
+        assert!(self::body(head).await.is_empty());
+        Response::from_parts(parts, response::full_body(body))
+    }
+
+    async fn body(resp: Response<ResponseBody>) -> Bytes {
+        resp.into_body().collect().await.unwrap().to_bytes()
+    }
+
+    #[test_case(ContentTypeSource::Extension; "extension")]
+    #[test_case(ContentTypeSource::Object; "object")]
+    #[tokio::test]
+    async fn test_precompressed_variant(content_type_source: ContentTypeSource) {
+        let metadata_policy = MetadataPolicy {
+            content_type_source,
+            ..Default::default()
+        };
+        let resp = get(
+            &objects(),
+            &metadata_policy,
+            None,
+            &headers(&[("accept-encoding", "gzip")]),
+            "style.css",
+        )
+        .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[CONTENT_ENCODING], "gzip");
+        assert_eq!(resp.headers()[CONTENT_TYPE], "text/css");
+        assert_eq!(body(resp).await, "gzipped");
+    }
+
+    #[tokio::test]
+    async fn test_precompressed_variant_without_object() {
+        let resp = get(
+            &objects(),
+            &MetadataPolicy::default(),
+            None,
+            &headers(&[("accept-encoding", "gzip")]),
+            "orphan.css",
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_precompressed_variant_of_redirect() {
+        let resp = get(
+            &objects(),
+            &MetadataPolicy::default(),
+            None,
+            &headers(&[("accept-encoding", "gzip")]),
+            "old.css",
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(resp.headers()[LOCATION], "/style.css");
+        assert!(resp.headers().get(CONTENT_ENCODING).is_none());
+    }
+
+    #[test_case("settings/profile", "*/*", StatusCode::OK; "route without extension")]
+    #[test_case("docs/page.html", "text/html", StatusCode::OK; "navigation")]
+    #[test_case("app.js", "*/*", StatusCode::NOT_FOUND; "missing asset")]
+    #[tokio::test]
+    async fn test_spa_fallback(key: &str, accept: &'static str, expected: StatusCode) {
+        let resp = get(
+            &objects(),
+            &MetadataPolicy::default(),
+            Some("index.html"),
+            &headers(&[("accept", accept)]),
+            key,
+        )
+        .await;
+
+        assert_eq!(resp.status(), expected);
+        if expected == StatusCode::OK {
+            assert_eq!(resp.headers()[CONTENT_TYPE], "text/html");
+            assert_eq!(body(resp).await, "<html>");
+        }
+    }
+
+    #[test_case(&[("if-none-match", "\"shell\"")], StatusCode::NOT_MODIFIED, ""; "not modified")]
+    #[test_case(&[("range", "bytes=1-4")], StatusCode::PARTIAL_CONTENT, "html"; "range")]
+    #[tokio::test]
+    async fn test_spa_fallback_conditional(
+        pairs: &[(&'static str, &'static str)],
+        expected: StatusCode,
+        expected_body: &str,
+    ) {
+        let resp = get(
+            &objects(),
+            &MetadataPolicy::default(),
+            Some("index.html"),
+            &headers(pairs),
+            "settings/profile",
+        )
+        .await;
+
+        assert_eq!(resp.status(), expected);
+        assert_eq!(body(resp).await, expected_body);
+    }
+
+    #[test_case("", StatusCode::OK; "root")]
+    #[test_case("css/", StatusCode::OK; "prefix")]
+    #[test_case("missing/", StatusCode::NOT_FOUND; "missing prefix")]
+    #[tokio::test]
+    async fn test_list_head(prefix: &str, expected: StatusCode) {
+        let objects = objects().with("css/site.css", "body {}", ObjectMetadata::default());
+        let (ownership_cache, metadata_policy) = (ownership_cache(), MetadataPolicy::default());
+        let (headers, site) = (HeaderMap::new(), site(SiteSettings::default()));
+
+        let resp = get_and_head(|head| {
+            let ctx = context(&objects, &ownership_cache, &metadata_policy, &headers, head);
+            let site = &site;
+            async move { s3_list_handle(&ctx, site, None, prefix).await.unwrap() }
+        })
+        .await;
+        assert_eq!(resp.status(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_error_document_head() {
+        let objects = objects().with("404.html", "<h1>Not Found</h1>", ObjectMetadata::default());
+        let (ownership_cache, metadata_policy) = (ownership_cache(), MetadataPolicy::default());
+        let (headers, site) = (HeaderMap::new(), site(SiteSettings::default()));
+        let keys = ["docs/404.html".to_string(), "404.html".to_string()];
+
+        let resp = get_and_head(|head| {
+            let ctx = context(&objects, &ownership_cache, &metadata_policy, &headers, head);
+            let (site, keys) = (&site, &keys);
+            async move {
+                s3_error_document_handle(&ctx, site, StatusCode::NOT_FOUND, keys)
+                    .await
+                    .unwrap()
+                    .unwrap()
+            }
+        })
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(resp.headers()[CONTENT_TYPE], "text/html");
+        assert_eq!(body(resp).await, "<h1>Not Found</h1>");
+    }
+
+    #[test_case("/new/page.html", Some("/new/page.html"); "path")]
+    #[test_case("https://example.com/page.html", Some("https://example.com/page.html"); "https")]
+    #[test_case("http://example.com/", Some("http://example.com/"); "http")]
+    #[test_case("page.html", None; "relative without slash")]
+    #[test_case("ftp://example.com/", None; "unsupported scheme")]
+    fn test_website_redirect_location(location: &str, expected: Option<&str>) {
+        let metadata = ObjectMetadata::builder()
+            .website_redirect_location(location)
+            .build();
+        assert_eq!(website_redirect_location(&metadata), expected);
+    }
+
+    #[test_case(Some(403), StatusCode::FORBIDDEN; "forbidden")]
+    #[test_case(Some(404), StatusCode::NOT_FOUND; "not found")]
+    #[test_case(Some(503), StatusCode::SERVICE_UNAVAILABLE; "slow down")]
+    #[test_case(Some(400), StatusCode::INTERNAL_SERVER_ERROR; "bad request")]
+    #[test_case(None, StatusCode::INTERNAL_SERVER_ERROR; "no response")]
+    fn test_error_status(status: Option<u16>, expected: StatusCode) {
+        assert_eq!(error_status(status), expected);
+    }
+
+    #[test]
+    fn test_no_website_redirect_location() {
+        assert_eq!(website_redirect_location(&ObjectMetadata::default()), None);
+    }
 }
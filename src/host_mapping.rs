@@ -0,0 +1,211 @@
+use crate::router;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use std::sync::Arc;
+
+/// Where the objects of a host live: a bucket and a key prefix within it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    bucket: String,
+    prefix: String,
+}
+
+impl Origin {
+    pub fn new(bucket: &str, prefix: &str) -> Self {
+        let prefix = prefix.trim_matches('/');
+        let prefix = if prefix.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", prefix)
+        };
+
+        Self {
+            bucket: bucket.to_string(),
+            prefix,
+        }
+    }
+
+    pub fn bucket(&self) -> &str {
+        &self.bucket
+    }
+
+    /// The key prefix, empty or ending with `/`.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Returns the object key of `key`, which is relative to the root of the site.
+    pub fn key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HostMapping {
+    regex: Regex,
+    /// `None` serves the bucket named after the host.
+    /// Templates may refer to the labels captured by the wildcards of the host as `{1}`, `{2}`...
+    bucket: Option<String>,
+    prefix: String,
+}
+
+impl HostMapping {
+    fn origin(&self, host: &str) -> Option<Origin> {
+        let captures = router::domain_captures(&self.regex, host)?;
+        let bucket = match &self.bucket {
+            Some(bucket) => render(bucket, &captures).ok()?,
+            None => host.to_string(),
+        };
+        let prefix = render(&self.prefix, &captures).ok()?;
+
+        Some(Origin::new(&bucket, &prefix))
+    }
+}
+
+/// Maps hosts to the origin of their objects. Explicit mappings are matched in order before
+/// the allowed domains, which serve the bucket named after the host.
+#[derive(Debug, Clone, Default)]
+pub struct HostMappings(Arc<Vec<HostMapping>>);
+
+impl HostMappings {
+    pub fn new(mappings: Vec<HostMapping>, allow_domains: &[String]) -> Self {
+        let allow_domains =
+            allow_domains
+                .iter()
+                .filter_map(|domain| match router::domain_regex(domain) {
+                    Ok(Some(regex)) => Some(HostMapping {
+                        regex,
+                        bucket: None,
+                        prefix: String::new(),
+                    }),
+                    _ => {
+                        tracing::warn!("invalid allow domain: {}", domain);
+                        None
+                    }
+                });
+
+        Self(Arc::new(
+            mappings.into_iter().chain(allow_domains).collect(),
+        ))
+    }
+
+    /// Returns the origin of `host`, or `None` when the host is not served.
+    pub fn resolve(&self, host: &str) -> Option<Origin> {
+        self.0.iter().find_map(|mapping| mapping.origin(host))
+    }
+}
+
+/// Deserializes `host=bucket[/prefix]` pairs, e.g. `example.com=sites/example`.
+pub fn deserialize_mappings<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<HostMapping>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|pair| parse_mapping(pair).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+fn parse_mapping(pair: &str) -> Result<HostMapping, String> {
+    let (domain, origin) = pair
+        .split_once('=')
+        .ok_or_else(|| format!("expected host=bucket[/prefix]: {}", pair))?;
+    let domain = domain.trim();
+    let regex = router::domain_regex(domain)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("invalid host: {}", domain))?;
+    let (bucket, prefix) = origin.trim().split_once('/').unwrap_or((origin.trim(), ""));
+    if bucket.is_empty() {
+        return Err(format!("empty bucket: {}", pair));
+    }
+    let captures = vec![String::new(); regex.captures_len() - 1];
+    render(bucket, &captures)?;
+    render(prefix, &captures)?;
+
+    Ok(HostMapping {
+        regex,
+        bucket: Some(bucket.to_string()),
+        prefix: prefix.to_string(),
+    })
+}
+
+/// Substitutes `{n}` in `template` with the `n`th captured label.
+fn render(template: &str, captures: &[String]) -> Result<String, String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed placeholder: {}", template))?;
+        let capture = rest[start + 1..start + end]
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| captures.get(n.checked_sub(1)?))
+            .ok_or_else(|| {
+                format!(
+                    "unknown placeholder {} in {}",
+                    &rest[start..=start + end],
+                    template
+                )
+            })?;
+        rendered.push_str(capture);
+        rest = &rest[start + end + 1..];
+    }
+    rendered.push_str(rest);
+
+    Ok(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn mappings() -> HostMappings {
+        HostMappings::new(
+            vec![
+                parse_mapping("example.com=sites/example.com").unwrap(),
+                parse_mapping("*.example.net=shared-sites/net/").unwrap(),
+                parse_mapping("docs.example.org=docs-bucket").unwrap(),
+                parse_mapping("*.preview.example.com=previews-bucket/{1}").unwrap(),
+                parse_mapping("*.sites.example.com=sites-{1}").unwrap(),
+            ],
+            &["*.example.org".to_string(), "*example.com".to_string()],
+        )
+    }
+
+    #[test_case("example.com", Some(("sites", "example.com/")); "apex domain")]
+    #[test_case("foo.example.net", Some(("shared-sites", "net/")); "wildcard host")]
+    #[test_case("docs.example.org", Some(("docs-bucket", "")); "mapping before allow domain")]
+    #[test_case("blog.example.org", Some(("blog.example.org", "")); "allow domain")]
+    #[test_case("www.example.com", None; "invalid allow domain")]
+    #[test_case("pr-123.preview.example.com", Some(("previews-bucket", "pr-123/")); "prefix template")]
+    #[test_case("Blog.sites.example.com", Some(("sites-blog", "")); "bucket template")]
+    #[test_case("example.net", None; "not served")]
+    fn test_resolve(host: &str, expected: Option<(&str, &str)>) {
+        assert_eq!(
+            mappings().resolve(host),
+            expected.map(|(bucket, prefix)| Origin::new(bucket, prefix))
+        );
+    }
+
+    #[test]
+    fn test_origin_key() {
+        let origin = Origin::new("sites", "/example.com/");
+        assert_eq!(origin.prefix(), "example.com/");
+        assert_eq!(origin.key("docs/index.html"), "example.com/docs/index.html");
+        assert_eq!(Origin::new("sites", "").key("index.html"), "index.html");
+    }
+
+    #[test_case("example.com"; "missing origin")]
+    #[test_case("example.com=/prefix"; "empty bucket")]
+    #[test_case("*.*.example.com=sites"; "invalid host")]
+    #[test_case("*.example.com=sites/{2}"; "placeholder without wildcard")]
+    #[test_case("example.com=sites/{1}"; "placeholder without captures")]
+    #[test_case("*.example.com=sites-{1"; "unclosed placeholder")]
+    #[test_case("*.example.com=sites-{name}"; "named placeholder")]
+    #[test_case("*.example.com=sites-{0}"; "zero placeholder")]
+    fn test_parse_mapping_invalid(pair: &str) {
+        assert!(parse_mapping(pair).is_err());
+    }
+}
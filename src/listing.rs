@@ -0,0 +1,264 @@
+use crate::response::{self, ResponseBody, ResponseError};
+use crate::s3::{ListedObject, ObjectListing};
+use aws_smithy_types::date_time::Format;
+use hyper::header::ACCEPT;
+use hyper::{HeaderMap, Response, StatusCode};
+use percent_encoding::{
+    percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS, NON_ALPHANUMERIC,
+};
+use serde::Serialize;
+use std::fmt::Write;
+
+const CONTINUATION_TOKEN: &str = "continuation-token";
+
+/// Characters escaped when a key becomes the path of a URL: in relative links to child objects
+/// and prefixes, and in redirect locations.
+pub const PATH: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+#[derive(Debug, Serialize)]
+struct JsonListing<'a> {
+    prefix: &'a str,
+    objects: Vec<&'a ListedObject>,
+    common_prefixes: &'a [String],
+    next_continuation_token: Option<&'a str>,
+}
+
+/// Returns whether the client prefers `application/json` over `text/html`.
+pub fn prefers_json(headers: &HeaderMap) -> bool {
+    let accept = headers
+        .get(ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    let mut json = 0.0;
+    let mut html = 0.0;
+    for item in accept.split(',') {
+        let mut params = item.split(';').map(str::trim);
+        let media_range = params.next().unwrap_or_default().to_ascii_lowercase();
+        let q = params
+            .find_map(|param| param.strip_prefix("q="))
+            .map(|q| q.parse::<f32>().unwrap_or(0.0))
+            .unwrap_or(1.0);
+        match media_range.as_str() {
+            "application/json" => json = q,
+            "text/html" | "text/*" | "*/*" => html = f32::max(html, q),
+            _ => {}
+        }
+    }
+    json > html
+}
+
+/// Extracts the continuation token of the next page from the query string.
+pub fn continuation_token(query: Option<&str>) -> Option<String> {
+    query?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| *name == CONTINUATION_TOKEN)
+        .map(|(_, value)| percent_decode_str(value).decode_utf8_lossy().into_owned())
+}
+
+pub fn listing_response(
+    prefix: &str,
+    listing: &ObjectListing,
+    json: bool,
+) -> Result<Response<ResponseBody>, ResponseError> {
+    if json {
+        return response::json_response(
+            StatusCode::OK,
+            &JsonListing {
+                prefix,
+                objects: objects(prefix, listing).collect(),
+                common_prefixes: listing.common_prefixes(),
+                next_continuation_token: listing.next_continuation_token(),
+            },
+        );
+    }
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", mime::TEXT_HTML_UTF_8.as_ref())
+        .body(response::full_body(render_html(prefix, listing)))?)
+}
+
+/// Returns the objects of the listing without the zero-byte object that some tools create to
+/// represent the folder itself.
+fn objects<'a>(
+    prefix: &'a str,
+    listing: &'a ObjectListing,
+) -> impl Iterator<Item = &'a ListedObject> {
+    listing
+        .objects()
+        .iter()
+        .filter(move |object| object.key() != prefix)
+}
+
+fn render_html(prefix: &str, listing: &ObjectListing) -> String {
+    let title = html_escape(&format!("Index of /{}", prefix));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n</head>\n\
+         <body>\n<h1>{title}</h1>\n<table>\n\
+         <thead><tr><th>Name</th><th>Size</th><th>Last modified</th></tr></thead>\n<tbody>\n"
+    );
+
+    if !prefix.is_empty() {
+        html.push_str("<tr><td><a href=\"../\">../</a></td><td>-</td><td>-</td></tr>\n");
+    }
+    for common_prefix in listing.common_prefixes() {
+        let name = common_prefix.strip_prefix(prefix).unwrap_or(common_prefix);
+        let _ = writeln!(
+            html,
+            "<tr><td><a href=\"{}\">{}</a></td><td>-</td><td>-</td></tr>",
+            html_escape(&utf8_percent_encode(name, PATH).to_string()),
+            html_escape(name),
+        );
+    }
+    for object in objects(prefix, listing) {
+        let name = object.key().strip_prefix(prefix).unwrap_or(object.key());
+        let last_modified = object
+            .last_modified()
+            .and_then(|d| d.fmt(Format::DateTime).ok())
+            .unwrap_or_else(|| "-".to_string());
+        let _ = writeln!(
+            html,
+            "<tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td></tr>",
+            html_escape(&utf8_percent_encode(name, PATH).to_string()),
+            html_escape(name),
+            object.size(),
+            last_modified,
+        );
+    }
+    html.push_str("</tbody>\n</table>\n");
+
+    if let Some(token) = listing.next_continuation_token() {
+        let _ = writeln!(
+            html,
+            "<p><a href=\"?{}={}\">Next page</a></p>",
+            CONTINUATION_TOKEN,
+            utf8_percent_encode(token, NON_ALPHANUMERIC),
+        );
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn html_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router;
+    use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output;
+    use aws_sdk_s3::types::{CommonPrefix, Object};
+    use aws_smithy_types::DateTime;
+    use http_body_util::BodyExt;
+    use test_case::test_case;
+
+    fn listing() -> ObjectListing {
+        ListObjectsV2Output::builder()
+            .contents(Object::builder().key("docs/").size(0).build())
+            .contents(
+                Object::builder()
+                    .key("docs/a <b>.txt")
+                    .size(42)
+                    .last_modified(DateTime::from_secs(1445412480))
+                    .build(),
+            )
+            .common_prefixes(CommonPrefix::builder().prefix("docs/img/").build())
+            .next_continuation_token("abc/+=")
+            .build()
+            .into()
+    }
+
+    #[test_case("application/json", true; "json")]
+    #[test_case("text/html,application/xhtml+xml,*/*;q=0.8", false; "browser")]
+    #[test_case("application/json;q=0.5, text/html", false; "html preferred")]
+    #[test_case("text/html;q=0.5, application/json", true; "json preferred")]
+    #[test_case("*/*", false; "wildcard")]
+    #[test_case("", false; "no accept")]
+    fn test_prefers_json(accept: &'static str, expected: bool) {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, accept.parse().unwrap());
+        assert_eq!(prefers_json(&headers), expected);
+    }
+
+    #[test_case(None, None; "no query")]
+    #[test_case(Some("foo=bar"), None; "no token")]
+    #[test_case(Some("foo=bar&continuation-token=abc%2F%2B%3D"), Some("abc/+="); "encoded token")]
+    fn test_continuation_token(query: Option<&str>, expected: Option<&str>) {
+        assert_eq!(continuation_token(query).as_deref(), expected);
+    }
+
+    #[test]
+    fn test_render_html() {
+        let html = render_html("docs/", &listing());
+
+        assert!(html.contains("<title>Index of /docs/</title>"));
+        assert!(html.contains("<a href=\"../\">../</a>"));
+        assert!(html.contains("<a href=\"img/\">img/</a>"));
+        assert!(html.contains(
+            "<a href=\"a%20%3Cb%3E.txt\">a &lt;b&gt;.txt</a></td><td>42</td><td>2015-10-21T07:28:00Z</td>"
+        ));
+        assert!(html.contains("<a href=\"?continuation-token=abc%2F%2B%3D\">Next page</a>"));
+        assert_eq!(html.matches("<tr><td>").count(), 3);
+    }
+
+    #[test]
+    fn test_link_round_trip() {
+        let key = "docs/a 100% #1?é.txt";
+        let listing = ListObjectsV2Output::builder()
+            .contents(Object::builder().key(key).size(1).build())
+            .build()
+            .into();
+        let html = render_html("docs/", &listing);
+
+        // The first link goes to the parent prefix.
+        let href = html
+            .split("<a href=\"")
+            .nth(2)
+            .unwrap()
+            .split('"')
+            .next()
+            .unwrap();
+        let uri = format!("/docs/{}", href).parse::<hyper::Uri>().unwrap();
+        assert_eq!(router::decode_path(uri.path()), Some(format!("/{}", key)));
+    }
+
+    #[tokio::test]
+    async fn test_listing_response_json() {
+        let resp = listing_response("docs/", &listing(), true).unwrap();
+        assert_eq!(resp.headers()["Content-Type"], "application/json");
+
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(value["prefix"], "docs/");
+        assert_eq!(value["objects"].as_array().unwrap().len(), 1);
+        assert_eq!(value["objects"][0]["key"], "docs/a <b>.txt");
+        assert_eq!(value["objects"][0]["size"], 42);
+        assert_eq!(value["objects"][0]["last_modified"], "2015-10-21T07:28:00Z");
+        assert_eq!(value["common_prefixes"][0], "docs/img/");
+        assert_eq!(value["next_continuation_token"], "abc/+=");
+    }
+}
@@ -1,14 +1,34 @@
-use futures_util::future::try_join;
+use futures_util::future::try_join3;
 use std::net::SocketAddr;
 use std::process::exit;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_util::sync::CancellationToken;
 
+mod cache;
+mod coalesce;
+mod compression;
+mod conditional;
 mod config;
+mod disk_cache;
+mod encoding;
+mod error_document;
 mod handler;
+mod host_mapping;
+mod listing;
+mod negative_cache;
+mod ownership;
+mod range;
+mod reload;
 mod response;
 mod router;
+mod routing_rules;
 mod s3;
 mod server;
 mod service;
+mod site;
+mod tls;
 
 type Error = Box<dyn std::error::Error + Send + Sync>;
 
@@ -21,25 +41,152 @@ async fn main() -> Result<(), Error> {
         .with_ansi(false)
         .init();
 
-    let config = config::AppConfig::new();
+    let config = match config::AppConfig::new() {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::error!("{}", e);
+            exit(1);
+        }
+    };
     tracing::info!("application config: {:?}", config);
 
+    let ownership_cache = ownership::OwnershipCache::new(ownership::OwnershipCacheConfig {
+        positive_ttl: Duration::from_secs(config.ownership_cache_ttl),
+        negative_ttl: Duration::from_secs(config.ownership_cache_negative_ttl),
+        max_entries: config.ownership_cache_max_entries,
+    });
+
+    let active_config = reload::ActiveConfig::new(config.sites());
+    let hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            tracing::error!("failed to listen for SIGHUP: {}", e);
+            exit(1);
+        }
+    };
+    tokio::spawn(reload::watch(
+        active_config.clone(),
+        hangup,
+        Duration::from_secs(config.config_watch_interval),
+    ));
+
+    let tls = if config.tls_certificates.is_empty() {
+        None
+    } else {
+        let resolver = match tls::CertResolver::new(config.tls_certificates) {
+            Ok(resolver) => Arc::new(resolver),
+            Err(e) => {
+                tracing::error!("failed to load TLS certificates: {}", e);
+                exit(1);
+            }
+        };
+        if config.tls_watch_interval > 0 {
+            tokio::spawn(tls::watch(
+                resolver.clone(),
+                Duration::from_secs(config.tls_watch_interval),
+            ));
+        }
+        match tls::acceptor(resolver, config.tls_min_version) {
+            Ok(acceptor) => Some(acceptor),
+            Err(e) => {
+                tracing::error!("{}", e);
+                exit(1);
+            }
+        }
+    };
+    if tls.is_none() && config.http_redirect_port.is_some() {
+        tracing::warn!("ignoring the HTTP redirect port, TLS is not configured");
+    }
+    let redirect_port = config.http_redirect_port.filter(|_| tls.is_some());
+
+    // Draining stops the gateway from accepting connections and fails the readiness probe;
+    // the management server stops once the gateway has drained.
+    let draining = CancellationToken::new();
+    let stopped = CancellationToken::new();
+    tokio::spawn({
+        let draining = draining.clone();
+        async move {
+            match server::shutdown_signal().await {
+                Ok(()) => draining.cancel(),
+                Err(e) => tracing::error!("failed to listen for shutdown signals: {:?}", e),
+            }
+        }
+    });
+
     let gateway = server::GatewayServer::builder()
         .addr(SocketAddr::from(([0, 0, 0, 0], config.gateway_port)))
-        .allow_domains(config.allow_domains)
-        .root_object(config.root_object)
-        .subdir_root_object(config.subdir_root_object)
-        .no_such_key_redirect_object(config.no_such_key_redirect_object)
-        .allow_cross_account(config.allow_cross_account)
+        .config(active_config.clone())
+        .metadata_policy(response::MetadataPolicy {
+            content_type_source: config.content_type_source,
+            forward_metadata: config.forward_metadata,
+        })
+        .precompressed_encodings(config.precompressed_encodings)
+        .compression_policy(compression::CompressionPolicy {
+            encodings: config.compression_encodings,
+            min_size: config.compression_min_size,
+            mime_types: config.compression_mime_types,
+        })
+        .cache_config(cache::CacheConfig {
+            capacity: config.cache_capacity,
+            max_object_size: config.cache_max_object_size,
+            ttl: Duration::from_secs(config.cache_ttl),
+        })
+        .disk_cache_config(
+            config
+                .disk_cache_dir
+                .map(|dir| disk_cache::DiskCacheConfig {
+                    dir,
+                    capacity: config.disk_cache_capacity,
+                    ttl: Duration::from_secs(config.cache_ttl),
+                }),
+        )
+        .coalesce_max_object_size(config.coalesce_max_object_size)
+        .ownership_cache(ownership_cache.clone())
+        .negative_cache_config(negative_cache::NegativeCacheConfig {
+            ttl: Duration::from_secs(config.negative_cache_ttl),
+            max_entries: config.negative_cache_max_entries,
+        })
+        .http2_config(server::Http2Config {
+            max_concurrent_streams: config.http2_max_concurrent_streams,
+            initial_stream_window_size: config.http2_initial_stream_window_size,
+            initial_connection_window_size: config.http2_initial_connection_window_size,
+        })
+        .tls(tls)
+        .shutdown(draining.clone())
+        .shutdown_timeout(Duration::from_secs(config.shutdown_timeout))
         .build();
+    let gateway = async {
+        let result = gateway.await;
+        stopped.cancel();
+        result
+    };
     let management = server::ManagementServer::builder()
         .addr(SocketAddr::from(([0, 0, 0, 0], config.management_port)))
+        .ownership_cache(ownership_cache)
+        .config(active_config.clone())
+        .draining(draining.clone())
+        .shutdown(stopped.clone())
         .build();
+    let redirect = async {
+        match redirect_port {
+            Some(port) => {
+                server::RedirectServer::builder()
+                    .addr(SocketAddr::from(([0, 0, 0, 0], port)))
+                    .config(active_config.clone())
+                    .https_port(config.gateway_port)
+                    .shutdown(draining)
+                    .build()
+                    .await
+            }
+            None => Ok(()),
+        }
+    };
 
-    if let Err(e) = try_join(gateway, management).await {
+    if let Err(e) = try_join3(gateway, management, redirect).await {
         tracing::error!("failed to start server: {:?}", e);
         exit(1);
     };
+    tracing::info!("shut down gracefully");
 
     Ok(())
 }
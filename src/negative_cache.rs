@@ -0,0 +1,258 @@
+use crate::s3::{GetObjectResult, ObjectListing, ObjectMetadata, S3};
+use aws_sdk_s3::error::SdkError;
+use aws_sdk_s3::operation::get_object::GetObjectError;
+use aws_sdk_s3::operation::head_bucket::HeadBucketError;
+use aws_sdk_s3::operation::head_object::HeadObjectError;
+use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Error;
+use aws_sdk_s3::types::error::{NoSuchKey, NotFound};
+use aws_smithy_runtime_api::http::{Response, StatusCode};
+use aws_smithy_types::body::SdkBody;
+use moka::future::Cache;
+use std::time::Duration;
+
+const NOT_FOUND: u16 = 404;
+
+#[derive(Debug, Clone)]
+pub struct NegativeCacheConfig {
+    /// How long a missing key is remembered. The cache is disabled when zero.
+    pub ttl: Duration,
+    pub max_entries: u64,
+}
+
+/// An [`S3`] implementation that remembers missing keys for a short time, so that floods of
+/// requests for random paths do not reach S3. Results of [`S3::object_exists`] are cached
+/// whether the object exists or not.
+#[derive(Debug, Clone)]
+pub struct NegativeCachedS3<T> {
+    inner: T,
+    /// `false` for keys known to be missing, `true` for keys known to exist.
+    exists: Option<Cache<(String, String), bool>>,
+}
+
+impl<T> NegativeCachedS3<T> {
+    pub fn new(inner: T, config: NegativeCacheConfig) -> Self {
+        let exists = (!config.ttl.is_zero()).then(|| {
+            Cache::builder()
+                .max_capacity(config.max_entries)
+                .time_to_live(config.ttl)
+                .build()
+        });
+
+        Self { inner, exists }
+    }
+}
+
+impl<T> NegativeCachedS3<T>
+where
+    T: S3 + Send + Sync,
+{
+    async fn is_missing(&self, bucket: &str, key: &str) -> bool {
+        match &self.exists {
+            Some(exists) => exists
+                .get(&(bucket.to_string(), key.to_string()))
+                .await
+                .is_some_and(|exists| !exists),
+            None => false,
+        }
+    }
+
+    async fn insert_missing(&self, bucket: &str, key: &str) {
+        if let Some(exists) = &self.exists {
+            exists
+                .insert((bucket.to_string(), key.to_string()), false)
+                .await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<T> S3 for NegativeCachedS3<T>
+where
+    T: S3 + Send + Sync,
+{
+    async fn get_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        range: Option<String>,
+    ) -> Result<GetObjectResult, SdkError<GetObjectError>> {
+        if self.is_missing(bucket, key).await {
+            tracing::debug!("negative cache hit: s3://{}/{}", bucket, key);
+            return Err(SdkError::service_error(
+                GetObjectError::NoSuchKey(NoSuchKey::builder().build()),
+                not_found_response(),
+            ));
+        }
+
+        let result = self.inner.get_object(bucket, key, range).await;
+        if let Err(SdkError::ServiceError(e)) = &result {
+            if e.err().is_no_such_key() {
+                self.insert_missing(bucket, key).await;
+            }
+        }
+        result
+    }
+
+    async fn head_object(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> Result<ObjectMetadata, SdkError<HeadObjectError>> {
+        if self.is_missing(bucket, key).await {
+            tracing::debug!("negative cache hit: s3://{}/{}", bucket, key);
+            return Err(SdkError::service_error(
+                HeadObjectError::NotFound(NotFound::builder().build()),
+                not_found_response(),
+            ));
+        }
+
+        let result = self.inner.head_object(bucket, key).await;
+        if let Err(SdkError::ServiceError(e)) = &result {
+            if e.err().is_not_found() {
+                self.insert_missing(bucket, key).await;
+            }
+        }
+        result
+    }
+
+    async fn head_bucket(
+        &self,
+        bucket: &str,
+        expected_bucket_owner: &str,
+    ) -> Result<(), SdkError<HeadBucketError>> {
+        self.inner.head_bucket(bucket, expected_bucket_owner).await
+    }
+
+    async fn list_objects(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        continuation_token: Option<String>,
+    ) -> Result<ObjectListing, SdkError<ListObjectsV2Error>> {
+        self.inner
+            .list_objects(bucket, prefix, continuation_token)
+            .await
+    }
+
+    async fn object_exists(&self, bucket: &str, key: &str) -> bool {
+        let Some(exists) = &self.exists else {
+            return self.inner.object_exists(bucket, key).await;
+        };
+
+        exists
+            .optionally_get_with((bucket.to_string(), key.to_string()), async {
+                match self.inner.head_object(bucket, key).await {
+                    Ok(_) => Some(true),
+                    Err(e)
+                        if e.raw_response().map(|raw| raw.status().as_u16()) == Some(NOT_FOUND) =>
+                    {
+                        Some(false)
+                    }
+                    Err(_) => None,
+                }
+            })
+            .await
+            .unwrap_or_default()
+    }
+}
+
+fn not_found_response() -> Response {
+    Response::new(
+        StatusCode::try_from(NOT_FOUND).expect("valid status code"),
+        SdkBody::empty(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::s3::tests::{Counting, Objects};
+
+    const EXISTING_KEY: &str = "index.html";
+
+    /// Holds [`EXISTING_KEY`] and counts the requests for objects.
+    fn counting() -> Counting {
+        Counting::new(Objects::default().with(EXISTING_KEY, "hello", ObjectMetadata::default()))
+    }
+
+    fn config(max_entries: u64) -> NegativeCacheConfig {
+        NegativeCacheConfig {
+            ttl: Duration::from_secs(10),
+            max_entries,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_no_such_key() {
+        let objects = counting();
+        let cached = NegativeCachedS3::new(objects.clone(), config(100));
+
+        for _ in 0..3 {
+            let e = cached.get_object("bucket", ".env", None).await.unwrap_err();
+            assert_eq!(e.raw_response().unwrap().status().as_u16(), 404);
+            assert!(e.into_service_error().is_no_such_key());
+        }
+        let e = cached.head_object("bucket", ".env").await.unwrap_err();
+        assert!(e.into_service_error().is_not_found());
+        assert_eq!(objects.calls.get_object(), 1);
+        assert_eq!(objects.calls.head_object(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_existing_key_not_cached() {
+        let objects = counting();
+        let cached = NegativeCachedS3::new(objects.clone(), config(100));
+
+        for _ in 0..3 {
+            assert!(cached
+                .get_object("bucket", EXISTING_KEY, None)
+                .await
+                .is_ok());
+        }
+        assert_eq!(objects.calls.get_object(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_object_exists() {
+        let objects = counting();
+        let cached = NegativeCachedS3::new(objects.clone(), config(100));
+
+        for _ in 0..3 {
+            assert!(cached.object_exists("bucket", EXISTING_KEY).await);
+            assert!(!cached.object_exists("bucket", "404.html").await);
+        }
+        assert_eq!(objects.calls.head_object(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_bounded() {
+        let objects = counting();
+        let cached = NegativeCachedS3::new(objects.clone(), config(10));
+
+        for i in 0..100 {
+            let _ = cached
+                .get_object("bucket", &format!("random-{}", i), None)
+                .await;
+        }
+        let exists = cached.exists.as_ref().unwrap();
+        exists.run_pending_tasks().await;
+        assert!(exists.entry_count() <= 10);
+    }
+
+    #[tokio::test]
+    async fn test_disabled() {
+        let objects = counting();
+        let cached = NegativeCachedS3::new(
+            objects.clone(),
+            NegativeCacheConfig {
+                ttl: Duration::ZERO,
+                max_entries: 100,
+            },
+        );
+
+        for _ in 0..3 {
+            assert!(cached.get_object("bucket", ".env", None).await.is_err());
+        }
+        assert_eq!(objects.calls.get_object(), 3);
+    }
+}
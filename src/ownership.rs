@@ -0,0 +1,200 @@
+use crate::s3::S3;
+use moka::future::Cache;
+use moka::Expiry;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct OwnershipCacheConfig {
+    pub positive_ttl: Duration,
+    pub negative_ttl: Duration,
+    /// Maximum number of buckets remembered, so that requests for random hosts cannot grow
+    /// the cache without bound.
+    pub max_entries: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Ownership {
+    owned: bool,
+    checked_at: Instant,
+}
+
+struct OwnershipExpiry(OwnershipCacheConfig);
+
+impl OwnershipExpiry {
+    fn ttl(&self, ownership: &Ownership) -> Duration {
+        if ownership.owned {
+            self.0.positive_ttl
+        } else {
+            self.0.negative_ttl
+        }
+    }
+}
+
+impl Expiry<String, Ownership> for OwnershipExpiry {
+    fn expire_after_create(
+        &self,
+        _bucket: &String,
+        ownership: &Ownership,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        Some(self.ttl(ownership))
+    }
+}
+
+/// A cached ownership check, as reported by the management server.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct OwnershipEntry {
+    pub bucket: String,
+    pub owned: bool,
+    /// Seconds until the result is checked again.
+    pub expires_in: u64,
+}
+
+/// Remembers per bucket whether it belongs to the account of the gateway, so that
+/// HeadBucket is not called for every request.
+/// Concurrent checks for a bucket that is not cached yet share a single HeadBucket.
+#[derive(Debug, Clone)]
+pub struct OwnershipCache {
+    config: OwnershipCacheConfig,
+    cache: Cache<String, Ownership>,
+}
+
+impl OwnershipCache {
+    pub fn new(config: OwnershipCacheConfig) -> Self {
+        let cache = Cache::builder()
+            .max_capacity(config.max_entries)
+            .expire_after(OwnershipExpiry(config.clone()))
+            .build();
+
+        Self { config, cache }
+    }
+
+    pub async fn is_owned<T>(&self, s3_client: &T, self_account_id: &str, bucket: &str) -> bool
+    where
+        T: S3 + Send + Sync,
+    {
+        let check = async {
+            match s3_client.head_bucket(bucket, self_account_id).await {
+                Ok(_) => Some(true),
+                Err(e) => {
+                    // Only "forbidden" and "not found" are answers about the bucket itself;
+                    // anything else is retried by the next request.
+                    let status = e.raw_response().map(|raw| raw.status().as_u16());
+                    tracing::warn!(
+                        "failed to head bucket: bucket: {} e: {:?}",
+                        bucket,
+                        e.into_service_error()
+                    );
+                    matches!(status, Some(403) | Some(404)).then_some(false)
+                }
+            }
+        };
+
+        self.cache
+            .optionally_get_with_by_ref(bucket, async {
+                check.await.map(|owned| Ownership {
+                    owned,
+                    checked_at: Instant::now(),
+                })
+            })
+            .await
+            .is_some_and(|ownership| ownership.owned)
+    }
+
+    pub fn entries(&self) -> Vec<OwnershipEntry> {
+        let expiry = OwnershipExpiry(self.config.clone());
+        let mut entries = self
+            .cache
+            .iter()
+            .map(|(bucket, ownership)| OwnershipEntry {
+                bucket: bucket.to_string(),
+                owned: ownership.owned,
+                expires_in: expiry
+                    .ttl(&ownership)
+                    .saturating_sub(ownership.checked_at.elapsed())
+                    .as_secs(),
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.bucket.cmp(&b.bucket));
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::s3::tests::{Counting, Objects};
+    use futures_util::future::join_all;
+
+    /// Owns every bucket except `foreign`, which is denied, and `unavailable`, which fails.
+    fn buckets() -> Counting {
+        let objects = Objects::default()
+            .with_bucket_error("foreign", 403)
+            .with_bucket_error("unavailable", 500);
+        Counting::new(objects).with_delay(Duration::from_millis(10))
+    }
+
+    fn ownership_cache(max_entries: u64) -> OwnershipCache {
+        OwnershipCache::new(OwnershipCacheConfig {
+            positive_ttl: Duration::from_secs(300),
+            negative_ttl: Duration::from_secs(30),
+            max_entries,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_is_owned() {
+        let buckets = buckets();
+        let cache = ownership_cache(100);
+
+        let results =
+            join_all((0..10).map(|_| cache.is_owned(&buckets, "012345678901", "owned"))).await;
+        assert!(results.into_iter().all(|owned| owned));
+        assert!(!cache.is_owned(&buckets, "012345678901", "foreign").await);
+        assert!(!cache.is_owned(&buckets, "012345678901", "foreign").await);
+        assert_eq!(buckets.calls.head_bucket(), 2);
+
+        let entries = cache.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].bucket, "foreign");
+        assert!(!entries[0].owned);
+        assert!(entries[0].expires_in <= 30);
+        assert_eq!(entries[1].bucket, "owned");
+        assert!(entries[1].owned);
+        assert!(entries[1].expires_in > 30);
+    }
+
+    #[tokio::test]
+    async fn test_is_owned_transient_error() {
+        let buckets = buckets();
+        let cache = ownership_cache(100);
+
+        assert!(
+            !cache
+                .is_owned(&buckets, "012345678901", "unavailable")
+                .await
+        );
+        assert!(
+            !cache
+                .is_owned(&buckets, "012345678901", "unavailable")
+                .await
+        );
+        assert_eq!(buckets.calls.head_bucket(), 2);
+        assert!(cache.entries().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_bounded() {
+        let buckets = buckets();
+        let cache = ownership_cache(10);
+
+        for i in 0..20 {
+            cache
+                .is_owned(&buckets, "012345678901", &format!("bucket-{}", i))
+                .await;
+        }
+        cache.cache.run_pending_tasks().await;
+        assert!(cache.cache.entry_count() <= 10);
+    }
+}
@@ -0,0 +1,170 @@
+use aws_smithy_types::date_time::Format;
+use aws_smithy_types::DateTime;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const MAX_RANGES: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    Bounded(u64, u64),
+    From(u64),
+    Suffix(u64),
+}
+
+impl ByteRange {
+    /// Resolves the range against the object size into inclusive offsets.
+    /// Returns `None` if the range is not satisfiable.
+    pub fn resolve(&self, total: u64) -> Option<(u64, u64)> {
+        match *self {
+            ByteRange::Bounded(start, end) if start < total => Some((start, end.min(total - 1))),
+            ByteRange::From(start) if start < total => Some((start, total - 1)),
+            ByteRange::Suffix(length) if length > 0 && total > 0 => {
+                Some((total.saturating_sub(length), total - 1))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ByteRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ByteRange::Bounded(start, end) => write!(f, "bytes={}-{}", start, end),
+            ByteRange::From(start) => write!(f, "bytes={}-", start),
+            ByteRange::Suffix(length) => write!(f, "bytes=-{}", length),
+        }
+    }
+}
+
+/// Parses a `Range` header value. Returns `None` if the value is not a valid byte range set,
+/// in which case the header must be ignored.
+pub fn parse(value: &str) -> Option<Vec<ByteRange>> {
+    let (unit, set) = value.split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+
+    let mut ranges = Vec::new();
+    for spec in set
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+    {
+        let (start, end) = spec.split_once('-')?;
+        let range = match (start.trim(), end.trim()) {
+            ("", "") => return None,
+            ("", length) => ByteRange::Suffix(length.parse().ok()?),
+            (start, "") => ByteRange::From(start.parse().ok()?),
+            (start, end) => {
+                let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+                if start > end {
+                    return None;
+                }
+                ByteRange::Bounded(start, end)
+            }
+        };
+        ranges.push(range);
+    }
+
+    if ranges.is_empty() {
+        None
+    } else {
+        Some(ranges)
+    }
+}
+
+/// Resolves a `Range` header value consisting of exactly one satisfiable range.
+pub fn resolve_single(value: &str, total: u64) -> Option<(u64, u64)> {
+    match parse(value)?[..] {
+        [range] => range.resolve(total),
+        _ => None,
+    }
+}
+
+/// Evaluates an `If-Range` header value against the validators of the current representation.
+/// Entity tags use the strong comparison, so weak tags never match.
+pub fn if_range_matches(
+    if_range: &str,
+    e_tag: Option<&str>,
+    last_modified: Option<&DateTime>,
+) -> bool {
+    let if_range = if_range.trim();
+    if if_range.starts_with("W/") {
+        return false;
+    }
+    if if_range.starts_with('"') {
+        return e_tag == Some(if_range);
+    }
+
+    match (
+        DateTime::from_str(if_range, Format::HttpDate),
+        last_modified,
+    ) {
+        (Ok(date), Some(last_modified)) => date.secs() == last_modified.secs(),
+        _ => false,
+    }
+}
+
+pub fn content_range(start: u64, end: u64, total: u64) -> String {
+    format!("bytes {}-{}/{}", start, end, total)
+}
+
+pub fn boundary() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    format!("{:032x}", nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("bytes=0-499", vec![ByteRange::Bounded(0, 499)]; "bounded")]
+    #[test_case("bytes=500-", vec![ByteRange::From(500)]; "open ended")]
+    #[test_case("bytes=-500", vec![ByteRange::Suffix(500)]; "suffix")]
+    #[test_case("bytes=0-0, -1", vec![ByteRange::Bounded(0, 0), ByteRange::Suffix(1)]; "multiple")]
+    #[test_case("Bytes = 1-2", vec![ByteRange::Bounded(1, 2)]; "case insensitive unit")]
+    fn test_parse_valid(value: &str, expected: Vec<ByteRange>) {
+        assert_eq!(parse(value), Some(expected));
+    }
+
+    #[test_case("items=0-1"; "unknown unit")]
+    #[test_case("bytes=5-1"; "reversed")]
+    #[test_case("bytes=-"; "empty spec")]
+    #[test_case("bytes=a-b"; "not a number")]
+    #[test_case("bytes="; "empty set")]
+    #[test_case("0-1"; "missing unit")]
+    fn test_parse_invalid(value: &str) {
+        assert_eq!(parse(value), None);
+    }
+
+    #[test_case(ByteRange::Bounded(0, 99), 1000, Some((0, 99)); "bounded")]
+    #[test_case(ByteRange::Bounded(900, 2000), 1000, Some((900, 999)); "bounded past end")]
+    #[test_case(ByteRange::Bounded(1000, 2000), 1000, None; "bounded unsatisfiable")]
+    #[test_case(ByteRange::From(10), 1000, Some((10, 999)); "open ended")]
+    #[test_case(ByteRange::Suffix(100), 1000, Some((900, 999)); "suffix")]
+    #[test_case(ByteRange::Suffix(2000), 1000, Some((0, 999)); "suffix larger than object")]
+    #[test_case(ByteRange::Suffix(0), 1000, None; "zero suffix")]
+    #[test_case(ByteRange::From(0), 0, None; "empty object")]
+    fn test_resolve(range: ByteRange, total: u64, expected: Option<(u64, u64)>) {
+        assert_eq!(range.resolve(total), expected);
+    }
+
+    #[test_case("\"abc\"", true; "same etag")]
+    #[test_case("\"xyz\"", false; "different etag")]
+    #[test_case("W/\"abc\"", false; "weak etag")]
+    #[test_case("Wed, 21 Oct 2015 07:28:00 GMT", true; "same date")]
+    #[test_case("Thu, 22 Oct 2015 07:28:00 GMT", false; "different date")]
+    #[test_case("not a date", false; "invalid date")]
+    fn test_if_range_matches(if_range: &str, expected: bool) {
+        let last_modified = DateTime::from_secs(1445412480);
+        assert_eq!(
+            if_range_matches(if_range, Some("\"abc\""), Some(&last_modified)),
+            expected
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use crate::config::{self, AppConfig};
+use crate::site::Sites;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::signal::unix::Signal;
+
+/// The routing state built from one version of the configuration.
+#[derive(Debug)]
+pub struct Snapshot {
+    pub version: u64,
+    pub sites: Sites,
+    loaded_at: Instant,
+}
+
+/// The active configuration, as reported by the management server.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct ConfigVersion {
+    pub version: u64,
+    /// Seconds since the configuration was applied.
+    pub age: u64,
+}
+
+/// Holds the active [`Snapshot`]. A reload swaps the snapshot atomically; requests keep the
+/// snapshot they started with until they finish.
+#[derive(Debug, Clone)]
+pub struct ActiveConfig {
+    snapshot: Arc<RwLock<Arc<Snapshot>>>,
+    /// The account of the gateway is only looked up at startup when ownership is checked.
+    checks_ownership: bool,
+}
+
+impl ActiveConfig {
+    pub fn new(sites: Sites) -> Self {
+        Self {
+            checks_ownership: sites.checks_ownership(),
+            snapshot: Arc::new(RwLock::new(Arc::new(Snapshot {
+                version: 1,
+                sites,
+                loaded_at: Instant::now(),
+            }))),
+        }
+    }
+
+    pub fn load(&self) -> Arc<Snapshot> {
+        self.snapshot.read().unwrap().clone()
+    }
+
+    /// Replaces the routing state and returns the new version.
+    pub fn swap(&self, sites: Sites) -> Result<u64, String> {
+        if sites.checks_ownership() && !self.checks_ownership {
+            return Err("enabling bucket ownership checks requires a restart".to_string());
+        }
+
+        let mut active = self.snapshot.write().unwrap();
+        let version = active.version + 1;
+        *active = Arc::new(Snapshot {
+            version,
+            sites,
+            loaded_at: Instant::now(),
+        });
+        Ok(version)
+    }
+
+    pub fn version(&self) -> ConfigVersion {
+        let snapshot = self.load();
+        ConfigVersion {
+            version: snapshot.version,
+            age: snapshot.loaded_at.elapsed().as_secs(),
+        }
+    }
+}
+
+/// Reloads the configuration on SIGHUP, received through `hangup`, and, every `interval`, when
+/// the content of the configuration file changed. A `Duration::ZERO` interval disables watching
+/// the file. `hangup` is registered at startup, as an unhandled SIGHUP terminates the process.
+pub async fn watch(active: ActiveConfig, mut hangup: Signal, interval: Duration) {
+    let path = config::config_file();
+    let watch_file = path.is_some() && !interval.is_zero();
+    let mut content = read(path.clone()).await;
+    let mut ticker = tokio::time::interval(interval.max(Duration::from_secs(1)));
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = hangup.recv() => {
+                tracing::info!("received SIGHUP, reloading config");
+            }
+            _ = ticker.tick(), if watch_file => {
+                let current = read(path.clone()).await;
+                if current == content {
+                    continue;
+                }
+                tracing::info!("config file changed, reloading config");
+                content = current;
+            }
+        }
+
+        match reload(&active).await {
+            Ok(version) => tracing::info!("applied config version {}", version),
+            Err(e) => tracing::error!("rejected config, keeping the active one: {}", e),
+        }
+    }
+}
+
+/// Reads the configuration file off the async workers.
+async fn read(path: Option<PathBuf>) -> Option<Vec<u8>> {
+    let path = path?;
+    tokio::task::spawn_blocking(move || std::fs::read(path).ok())
+        .await
+        .ok()
+        .flatten()
+}
+
+async fn reload(active: &ActiveConfig) -> Result<u64, String> {
+    let config = tokio::task::spawn_blocking(AppConfig::new)
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+    tracing::info!("application config: {:?}", config);
+    active.swap(config.sites())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host_mapping::HostMappings;
+    use crate::routing_rules::RoutingRules;
+    use crate::site::SiteSettings;
+
+    fn sites(domain: &str, allow_cross_account: bool) -> Sites {
+        Sites::new(
+            HostMappings::new(Vec::new(), &[domain.to_string()]),
+            SiteSettings {
+                allow_cross_account,
+                ..Default::default()
+            },
+            Vec::new(),
+            Vec::new(),
+            RoutingRules::default(),
+        )
+    }
+
+    #[test]
+    fn test_swap() {
+        let active = ActiveConfig::new(sites("a.example.com", true));
+        let old = active.load();
+
+        assert_eq!(active.swap(sites("b.example.com", true)), Ok(2));
+        assert_eq!(active.version().version, 2);
+        assert!(active.load().sites.resolve("b.example.com").is_some());
+        assert!(active.load().sites.resolve("a.example.com").is_none());
+        // In-flight requests keep their snapshot.
+        assert!(old.sites.resolve("a.example.com").is_some());
+    }
+
+    #[test]
+    fn test_swap_rejects_ownership_checks() {
+        let active = ActiveConfig::new(sites("a.example.com", true));
+        assert!(active.swap(sites("b.example.com", false)).is_err());
+        assert_eq!(active.version().version, 1);
+        assert!(active.load().sites.resolve("a.example.com").is_some());
+
+        let active = ActiveConfig::new(sites("a.example.com", false));
+        assert_eq!(active.swap(sites("b.example.com", true)), Ok(2));
+        assert_eq!(active.swap(sites("c.example.com", false)), Ok(3));
+    }
+}
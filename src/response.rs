@@ -1,18 +1,85 @@
-use crate::s3::S3;
+use crate::config::ContentTypeSource;
+use crate::host_mapping::Origin;
+use crate::range;
+use crate::s3::{GetObjectResult, ObjectMetadata, S3};
+use aws_smithy_types::byte_stream::ByteStream;
+use aws_smithy_types::date_time::Format;
 use bytes::Bytes;
-use http_body_util::Full;
+use futures_util::{stream, Stream, StreamExt, TryStreamExt};
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::body::{Body, Frame};
+use hyper::header::CONTENT_LENGTH;
+use hyper::http::response::Builder;
 use hyper::{Response, StatusCode};
+use serde::Serialize;
+
+pub type ResponseBody = UnsyncBoxBody<Bytes, crate::Error>;
 
 #[derive(Debug, thiserror::Error)]
 pub enum ResponseError {
     #[error("failed to build response: {0}")]
     ResponseBuild(#[from] hyper::http::Error),
+    #[error("failed to serialize response: {0}")]
+    Json(#[from] serde_json::Error),
 }
 
-pub fn easy_response(status_code: StatusCode) -> Result<Response<Full<Bytes>>, ResponseError> {
-    let body = Full::new(Bytes::from(
-        status_code.canonical_reason().unwrap_or_default(),
-    ));
+/// Controls which object metadata is passed through to the client.
+#[derive(Debug, Clone, Default)]
+pub struct MetadataPolicy {
+    pub content_type_source: ContentTypeSource,
+    pub forward_metadata: Vec<String>,
+}
+
+impl MetadataPolicy {
+    pub fn content_type(&self, key: &str, metadata: &ObjectMetadata) -> String {
+        let guessed = mime_guess::from_path(key)
+            .first()
+            .map(|mime| mime.to_string());
+        let stored = metadata.content_type().map(str::to_string);
+
+        let content_type = match self.content_type_source {
+            ContentTypeSource::Extension => guessed.or(stored),
+            ContentTypeSource::Object => stored.or(guessed),
+        };
+        content_type.unwrap_or_else(|| mime::TEXT_PLAIN.to_string())
+    }
+}
+
+pub fn full_body<T: Into<Bytes>>(chunk: T) -> ResponseBody {
+    Full::new(chunk.into())
+        .map_err(|never| match never {})
+        .boxed_unsync()
+}
+
+/// Turns a response to GET into the response to the matching HEAD request: the same status and
+/// headers, with the length of the body that is left out.
+pub fn without_body(resp: Response<ResponseBody>) -> Response<ResponseBody> {
+    let (mut parts, body) = resp.into_parts();
+    if let Some(length) = body.size_hint().exact() {
+        parts.headers.entry(CONTENT_LENGTH).or_insert(length.into());
+    }
+    Response::from_parts(parts, full_body(Bytes::new()))
+}
+
+pub fn stream_body<S>(stream: S) -> ResponseBody
+where
+    S: Stream<Item = Result<Bytes, crate::Error>> + Send + 'static,
+{
+    StreamBody::new(stream.map_ok(Frame::data)).boxed_unsync()
+}
+
+pub fn byte_stream(body: ByteStream) -> impl Stream<Item = Result<Bytes, crate::Error>> + Send {
+    stream::try_unfold(body, |mut body| async move {
+        body.try_next()
+            .await
+            .map(|chunk| chunk.map(|chunk| (chunk, body)))
+    })
+    .map_err(crate::Error::from)
+}
+
+pub fn easy_response(status_code: StatusCode) -> Result<Response<ResponseBody>, ResponseError> {
+    let body = full_body(status_code.canonical_reason().unwrap_or_default());
 
     Ok(hyper::Response::builder()
         .header("Content-Type", mime::TEXT_PLAIN.as_ref())
@@ -20,46 +87,294 @@ pub fn easy_response(status_code: StatusCode) -> Result<Response<Full<Bytes>>, R
         .body(body)?)
 }
 
-pub fn s3_ok_response(
-    content_type: String,
-    body: Bytes,
-) -> Result<Response<Full<Bytes>>, ResponseError> {
+pub fn json_response<T: Serialize>(
+    status_code: StatusCode,
+    value: &T,
+) -> Result<Response<ResponseBody>, ResponseError> {
+    let body = serde_json::to_vec(value)?;
+
     Ok(Response::builder()
+        .header("Content-Type", mime::APPLICATION_JSON.as_ref())
+        .status(status_code)
+        .body(full_body(body))?)
+}
+
+pub fn s3_ok_response(
+    policy: &MetadataPolicy,
+    key: &str,
+    resp: GetObjectResult,
+) -> Result<Response<ResponseBody>, ResponseError> {
+    let builder = Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", policy.content_type(key, resp.metadata()))
+        .header("Accept-Ranges", "bytes");
+    let builder = with_content_length(builder, resp.metadata().content_length());
+    let builder = with_object_headers(builder, policy, resp.metadata());
+
+    Ok(builder.body(stream_body(byte_stream(resp.body())))?)
+}
+
+pub fn s3_partial_response(
+    policy: &MetadataPolicy,
+    key: &str,
+    content_range: &str,
+    resp: GetObjectResult,
+) -> Result<Response<ResponseBody>, ResponseError> {
+    let builder = Response::builder()
+        .status(StatusCode::PARTIAL_CONTENT)
+        .header("Content-Type", policy.content_type(key, resp.metadata()))
+        .header("Content-Range", content_range)
+        .header("Accept-Ranges", "bytes");
+    let builder = with_content_length(builder, resp.metadata().content_length());
+    let builder = with_object_headers(builder, policy, resp.metadata());
+
+    Ok(builder.body(stream_body(byte_stream(resp.body())))?)
+}
+
+pub fn s3_head_response(
+    policy: &MetadataPolicy,
+    key: &str,
+    metadata: &ObjectMetadata,
+) -> Result<Response<ResponseBody>, ResponseError> {
+    let builder = Response::builder()
         .status(StatusCode::OK)
-        .header("Content-Type", content_type)
-        .body(Full::new(body))?)
+        .header("Content-Type", policy.content_type(key, metadata))
+        .header("Accept-Ranges", "bytes");
+    let builder = with_content_length(builder, metadata.content_length());
+    let builder = with_object_headers(builder, policy, metadata);
+
+    Ok(builder.body(full_body(Bytes::new()))?)
+}
+
+pub fn redirect_response(
+    status_code: StatusCode,
+    location: &str,
+) -> Result<Response<ResponseBody>, ResponseError> {
+    Ok(Response::builder()
+        .status(status_code)
+        .header("Content-Type", mime::TEXT_PLAIN.as_ref())
+        .header("Location", location)
+        .body(full_body(status_code.as_str().to_string()))?)
+}
+
+pub fn not_modified_response(
+    metadata: &ObjectMetadata,
+) -> Result<Response<ResponseBody>, ResponseError> {
+    let mut builder = Response::builder().status(StatusCode::NOT_MODIFIED);
+    if let Some(cache_control) = metadata.cache_control() {
+        builder = builder.header("Cache-Control", cache_control);
+    }
+    if let Some(expires) = metadata
+        .expires()
+        .and_then(|d| d.fmt(Format::HttpDate).ok())
+    {
+        builder = builder.header("Expires", expires);
+    }
+    let builder = with_validators(builder, metadata);
+
+    Ok(builder.body(full_body(Bytes::new()))?)
+}
+
+pub fn s3_multipart_byteranges_response<T>(
+    s3_client: T,
+    policy: &MetadataPolicy,
+    bucket: &str,
+    key: &str,
+    metadata: &ObjectMetadata,
+    ranges: Vec<(u64, u64)>,
+) -> Result<Response<ResponseBody>, ResponseError>
+where
+    T: S3 + Clone + Send + Sync + 'static,
+{
+    let total = metadata.content_length().unwrap_or_default().max(0) as u64;
+    let content_type = policy.content_type(key, metadata);
+    let boundary = range::boundary();
+    let closing = Bytes::from(format!("\r\n--{}--\r\n", boundary));
+
+    let parts = ranges
+        .into_iter()
+        .map(|(start, end)| {
+            let header = Bytes::from(format!(
+                "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+                boundary,
+                content_type,
+                range::content_range(start, end, total),
+            ));
+            (header, start, end)
+        })
+        .collect::<Vec<_>>();
+    let content_length = parts
+        .iter()
+        .map(|(header, start, end)| header.len() as u64 + end - start + 1)
+        .sum::<u64>()
+        + closing.len() as u64;
+
+    let bucket = bucket.to_string();
+    let key = key.to_string();
+    let body = stream::iter(parts)
+        .then(move |(header, start, end)| {
+            let s3_client = s3_client.clone();
+            let bucket = bucket.clone();
+            let key = key.clone();
+            async move {
+                let resp = s3_client
+                    .get_object(
+                        &bucket,
+                        &key,
+                        Some(range::ByteRange::Bounded(start, end).to_string()),
+                    )
+                    .await?;
+                Ok::<_, crate::Error>(
+                    stream::once(async { Ok(header) }).chain(byte_stream(resp.body())),
+                )
+            }
+        })
+        .try_flatten()
+        .chain(stream::once(async { Ok(closing) }));
+
+    let builder = Response::builder()
+        .status(StatusCode::PARTIAL_CONTENT)
+        .header(
+            "Content-Type",
+            format!("multipart/byteranges; boundary={}", boundary),
+        )
+        .header("Content-Length", content_length)
+        .header("Accept-Ranges", "bytes");
+    let builder = with_validators(builder, metadata);
+
+    Ok(builder.body(stream_body(body))?)
+}
+
+pub fn range_not_satisfiable_response(
+    total: Option<i64>,
+) -> Result<Response<ResponseBody>, ResponseError> {
+    let status_code = StatusCode::RANGE_NOT_SATISFIABLE;
+    let mut builder = Response::builder()
+        .status(status_code)
+        .header("Content-Type", mime::TEXT_PLAIN.as_ref());
+    if let Some(total) = total {
+        builder = builder.header("Content-Range", format!("bytes */{}", total));
+    }
+
+    Ok(builder.body(full_body(
+        status_code.canonical_reason().unwrap_or_default(),
+    ))?)
 }
 
 pub async fn s3_error_response<T>(
     s3_client: &T,
-    bucket: &str,
-    is_no_such_key: bool,
+    origin: &Origin,
+    status_code: StatusCode,
     no_such_key_redirect_object: Option<String>,
-) -> Result<Response<Full<Bytes>>, ResponseError>
+) -> Result<Response<ResponseBody>, ResponseError>
 where
     T: S3 + Send + Sync + 'static,
 {
-    if is_no_such_key {
-        match no_such_key_redirect_object {
-            Some(redirect_object) => match s3_client.head_object(bucket, &redirect_object).await {
-                Ok(_) => Ok(Response::builder()
+    if status_code != StatusCode::NOT_FOUND {
+        return easy_response(status_code);
+    }
+
+    match no_such_key_redirect_object {
+        Some(redirect_object) => {
+            let redirect_key = origin.key(&redirect_object);
+            if s3_client
+                .object_exists(origin.bucket(), &redirect_key)
+                .await
+            {
+                Ok(Response::builder()
                     .status(StatusCode::FOUND)
                     .header("Content-Type", mime::TEXT_PLAIN.to_string())
                     .header("Location", format!("/{}", redirect_object))
-                    .body(Full::new(Bytes::from(StatusCode::FOUND.as_str())))?),
-                Err(e) => {
-                    tracing::warn!(
-                        "no such redirect object: s3://{}/{}: {:?}",
-                        bucket,
-                        redirect_object,
-                        e.into_service_error(),
-                    );
-                    easy_response(StatusCode::NOT_FOUND)
-                }
-            },
-            None => easy_response(StatusCode::NOT_FOUND),
+                    .body(full_body(StatusCode::FOUND.as_str()))?)
+            } else {
+                tracing::warn!(
+                    "no such redirect object: s3://{}/{}",
+                    origin.bucket(),
+                    redirect_key
+                );
+                easy_response(StatusCode::NOT_FOUND)
+            }
+        }
+        None => easy_response(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Serves an error document inline, keeping the status code of the error.
+pub fn s3_error_document_response(
+    policy: &MetadataPolicy,
+    status_code: StatusCode,
+    key: &str,
+    resp: GetObjectResult,
+) -> Result<Response<ResponseBody>, ResponseError> {
+    let builder = Response::builder()
+        .status(status_code)
+        .header("Content-Type", policy.content_type(key, resp.metadata()));
+    let builder = with_content_length(builder, resp.metadata().content_length());
+
+    Ok(builder.body(stream_body(byte_stream(resp.body())))?)
+}
+
+pub fn s3_error_document_head_response(
+    policy: &MetadataPolicy,
+    status_code: StatusCode,
+    key: &str,
+    metadata: &ObjectMetadata,
+) -> Result<Response<ResponseBody>, ResponseError> {
+    let builder = Response::builder()
+        .status(status_code)
+        .header("Content-Type", policy.content_type(key, metadata));
+    let builder = with_content_length(builder, metadata.content_length());
+
+    Ok(builder.body(full_body(Bytes::new()))?)
+}
+
+fn with_content_length(builder: Builder, content_length: Option<i64>) -> Builder {
+    match content_length {
+        Some(length) => builder.header("Content-Length", length),
+        None => builder,
+    }
+}
+
+fn with_validators(mut builder: Builder, metadata: &ObjectMetadata) -> Builder {
+    if let Some(e_tag) = metadata.e_tag() {
+        builder = builder.header("ETag", e_tag);
+    }
+    if let Some(last_modified) = metadata
+        .last_modified()
+        .and_then(|d| d.fmt(Format::HttpDate).ok())
+    {
+        builder = builder.header("Last-Modified", last_modified);
+    }
+    builder
+}
+
+fn with_object_headers(
+    mut builder: Builder,
+    policy: &MetadataPolicy,
+    metadata: &ObjectMetadata,
+) -> Builder {
+    let headers = [
+        ("Cache-Control", metadata.cache_control()),
+        ("Content-Encoding", metadata.content_encoding()),
+        ("Content-Disposition", metadata.content_disposition()),
+        ("Content-Language", metadata.content_language()),
+    ];
+    for (name, value) in headers {
+        if let Some(value) = value {
+            builder = builder.header(name, value);
         }
-    } else {
-        easy_response(StatusCode::INTERNAL_SERVER_ERROR)
     }
+    if let Some(expires) = metadata
+        .expires()
+        .and_then(|d| d.fmt(Format::HttpDate).ok())
+    {
+        builder = builder.header("Expires", expires);
+    }
+    for name in policy.forward_metadata.iter() {
+        if let Some(value) = metadata.user_metadata(name) {
+            builder = builder.header(format!("x-amz-meta-{}", name.to_ascii_lowercase()), value);
+        }
+    }
+
+    with_validators(builder, metadata)
 }
@@ -1,9 +1,14 @@
+use crate::encoding::Encoding;
+use crate::ownership::OwnershipCache;
+use crate::reload::ActiveConfig;
+use crate::response::{MetadataPolicy, ResponseBody};
 use crate::s3::S3;
+use crate::site::Sites;
 use crate::{handler, response};
-use bytes::Bytes;
-use http_body_util::Full;
 use hyper::body::Incoming;
-use hyper::{Method, Request, Response, StatusCode};
+use hyper::header::{ACCEPT, HOST};
+use hyper::{HeaderMap, Method, Request, Response, StatusCode};
+use percent_encoding::percent_decode_str;
 use regex::Regex;
 
 #[derive(Debug, thiserror::Error)]
@@ -14,102 +19,327 @@ pub enum RouterError {
     Handler(#[from] handler::HandlerError),
 }
 
+const MAX_LABEL_LENGTH: usize = 63;
+
 pub async fn gateway_route<T>(
     req: Request<Incoming>,
     s3_client: T,
-    allow_domains: Vec<String>,
-    root_object: Option<String>,
-    subdir_root_object: Option<String>,
-    no_such_key_redirect_object: Option<String>,
+    sites: Sites,
     self_account_id: Option<String>,
-) -> Result<Response<Full<Bytes>>, RouterError>
+    ownership_cache: OwnershipCache,
+    metadata_policy: MetadataPolicy,
+    precompressed_encodings: Vec<Encoding>,
+) -> Result<Response<ResponseBody>, RouterError>
 where
-    T: S3 + Send + Sync + 'static,
+    T: S3 + Clone + Send + Sync + 'static,
 {
-    let host = match req.headers().get("Host") {
-        Some(header) => {
-            let value = header
-                .to_str()
-                .unwrap_or_default()
-                .split(':')
-                .collect::<Vec<&str>>()[0];
-
-            if value.is_empty() {
-                return Ok(response::easy_response(StatusCode::BAD_REQUEST)?);
-            }
-
-            value
-        }
+    let host = match request_host(&req) {
+        Some(host) => host,
         None => return Ok(response::easy_response(StatusCode::BAD_REQUEST)?),
     };
 
-    let domain_check = match is_allow_domain(allow_domains, host) {
-        Ok(result) => result,
-        Err(_) => return Ok(response::easy_response(StatusCode::INTERNAL_SERVER_ERROR)?),
+    let site = match sites.resolve(host) {
+        Some(site) => site,
+        None => return Ok(response::easy_response(StatusCode::FORBIDDEN)?),
     };
-    if !domain_check {
-        return Ok(response::easy_response(StatusCode::FORBIDDEN)?);
+    let origin = site.origin();
+    let settings = site.settings();
+    let self_account_id = self_account_id.filter(|_| !settings.allow_cross_account);
+
+    let request_path = match decode_path(req.uri().path()) {
+        Some(path) => path,
+        None => return Ok(response::easy_response(StatusCode::BAD_REQUEST)?),
+    };
+    let request_key = request_path.trim_start_matches('/');
+    if let Some(redirect) = site.redirect(request_key, None) {
+        return Ok(response::redirect_response(
+            redirect.status,
+            &redirect.location,
+        )?);
     }
 
-    let mut path = req.uri().path().to_string();
-    if let Some(ref root) = root_object {
+    let mut path = request_path.clone();
+    if let Some(ref root) = settings.root_object {
         if path == "/" {
             path.push_str(root)
         }
     }
-    if let Some(ref subdir_root) = subdir_root_object {
+    if let Some(ref subdir_root) = settings.subdir_root_object {
         if path.ends_with('/') || !path.contains('.') {
             path.push('/');
             path.push_str(subdir_root);
         }
     }
     let key = path.trim_start_matches('/');
+    let ctx = handler::RequestContext {
+        s3_client: &s3_client,
+        ownership_cache: &ownership_cache,
+        metadata_policy: &metadata_policy,
+        precompressed_encodings: &precompressed_encodings,
+        self_account_id: self_account_id.as_deref(),
+        headers: req.headers(),
+        head: *req.method() == Method::HEAD,
+        spa_route: is_spa_route(req.headers(), &request_path),
+    };
+
+    let resp = 'route: {
+        let listable = matches!(*req.method(), Method::GET | Method::HEAD);
+        if listable && request_path.ends_with('/') && site.autoindex() {
+            let prefix = request_key;
+            let has_index = key != prefix
+                && s3_client
+                    .object_exists(origin.bucket(), &origin.key(key))
+                    .await;
+            if !has_index {
+                break 'route handler::s3_list_handle(&ctx, &site, req.uri().query(), prefix)
+                    .await?;
+            }
+        }
 
-    if key.is_empty() {
-        return Ok(response::easy_response(StatusCode::NOT_FOUND)?);
+        if key.is_empty() {
+            break 'route response::easy_response(StatusCode::NOT_FOUND)?;
+        }
+
+        match *req.method() {
+            Method::GET => handler::s3_handle(&ctx, &site, key).await?,
+            Method::HEAD => handler::s3_head_handle(&ctx, &site, key).await?,
+            _ => response::easy_response(StatusCode::METHOD_NOT_ALLOWED)?,
+        }
+    };
+
+    // Rules conditioned on an error code apply to the status the gateway would respond with.
+    if resp.status().is_client_error() || resp.status().is_server_error() {
+        if let Some(redirect) = site.redirect(request_key, Some(resp.status())) {
+            return Ok(response::redirect_response(
+                redirect.status,
+                &redirect.location,
+            )?);
+        }
     }
 
-    match req.method() {
-        &Method::GET => Ok(handler::s3_handle(
-            &s3_client,
-            no_such_key_redirect_object,
-            self_account_id,
-            host,
-            key,
-        )
-        .await?),
-        _ => Ok(response::easy_response(StatusCode::METHOD_NOT_ALLOWED)?),
+    if matches!(*req.method(), Method::GET | Method::HEAD) {
+        let keys = settings.error_documents.keys(resp.status(), request_key);
+        if !keys.is_empty() {
+            if let Some(resp) =
+                handler::s3_error_document_handle(&ctx, &site, resp.status(), &keys).await?
+            {
+                return Ok(resp);
+            }
+        }
     }
+
+    Ok(resp)
 }
 
 pub async fn management_route(
     req: Request<Incoming>,
-) -> Result<Response<Full<Bytes>>, RouterError> {
+    ownership_cache: OwnershipCache,
+    config: ActiveConfig,
+    ready: bool,
+) -> Result<Response<ResponseBody>, RouterError> {
     match (req.method(), req.uri().path()) {
         (&Method::GET, "/health") => Ok(response::easy_response(StatusCode::OK)?),
+        (&Method::GET, "/ready") if ready => Ok(response::easy_response(StatusCode::OK)?),
+        (&Method::GET, "/ready") => Ok(response::easy_response(StatusCode::SERVICE_UNAVAILABLE)?),
+        (&Method::GET, "/cache/ownership") => Ok(response::json_response(
+            StatusCode::OK,
+            &ownership_cache.entries(),
+        )?),
+        (&Method::GET, "/config") => {
+            Ok(response::json_response(StatusCode::OK, &config.version())?)
+        }
         _ => Ok(response::easy_response(StatusCode::NOT_FOUND)?),
     }
 }
 
-fn is_allow_domain(allow_domains: Vec<String>, domain: &str) -> Result<bool, regex::Error> {
+/// Redirects to HTTPS with `308`, which keeps the method of the request. Hosts that the
+/// gateway does not serve are rejected like on the gateway.
+pub async fn redirect_route<B>(
+    req: Request<B>,
+    sites: Sites,
+    https_port: u16,
+) -> Result<Response<ResponseBody>, RouterError> {
+    if request_host(&req).is_some_and(|host| sites.resolve(host).is_none()) {
+        return Ok(response::easy_response(StatusCode::FORBIDDEN)?);
+    }
+
+    match https_location(&req, https_port) {
+        Some(location) => Ok(response::redirect_response(
+            StatusCode::PERMANENT_REDIRECT,
+            &location,
+        )?),
+        None => Ok(response::easy_response(StatusCode::BAD_REQUEST)?),
+    }
+}
+
+/// Returns the HTTPS URL of the request on the gateway.
+fn https_location<B>(req: &Request<B>, https_port: u16) -> Option<String> {
+    let host = request_host(req)?;
+    let port = match https_port {
+        443 => String::new(),
+        port => format!(":{}", port),
+    };
+    let path = req
+        .uri()
+        .path_and_query()
+        .map_or("/", |path_and_query| path_and_query.as_str());
+
+    Some(format!("https://{}{}{}", host, port, path))
+}
+
+/// Returns the host of the request without the port: the `Host` header, or the authority of
+/// the URI when there is none, as HTTP/2 requests carry the `:authority` pseudo-header instead.
+fn request_host<B>(req: &Request<B>) -> Option<&str> {
+    let host = match req.headers().get(HOST) {
+        Some(header) => header
+            .to_str()
+            .unwrap_or_default()
+            .split(':')
+            .next()
+            .unwrap_or_default(),
+        None => req.uri().authority()?.host(),
+    };
+
+    (!host.is_empty()).then_some(host)
+}
+
+/// Percent-decodes the path of a request once, so that keys, prefixes and routing rules see
+/// the key as stored in S3. Returns `None` when the decoded path is not valid UTF-8.
+pub fn decode_path(path: &str) -> Option<String> {
+    percent_decode_str(path)
+        .decode_utf8()
+        .ok()
+        .map(|path| path.into_owned())
+}
+
+/// Returns whether a missing key may be answered with the single-page application shell:
+/// navigations accept HTML or request a path without a file extension, while missing assets
+/// such as `.js` files still get a 404.
+pub fn is_spa_route(headers: &HeaderMap, path: &str) -> bool {
+    let accepts_html = headers
+        .get(ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.to_ascii_lowercase().contains("text/html"));
+    let has_extension = path
+        .rsplit('/')
+        .next()
+        .is_some_and(|name| name.contains('.'));
+
+    accepts_html || !has_extension
+}
+
+pub fn is_allow_domain(allow_domains: &[Regex], domain: &str) -> bool {
+    allow_domains.iter().any(|re| re.is_match(domain))
+}
+
+/// Compiles the valid domains of `domains` with [`domain_regex`], skipping the others.
+pub fn domain_regexes(domains: &[String]) -> Vec<Regex> {
+    domains
+        .iter()
+        .filter_map(|domain| domain_regex(domain).ok().flatten())
+        .collect()
+}
+
+/// Compiles a domain such as `foo.example.com` or `*.example.com` into a regex matching hosts.
+/// Returns `None` for a domain that is not valid.
+pub fn domain_regex(domain: &str) -> Result<Option<Regex>, regex::Error> {
     let re = Regex::new(r"^(\*\.)?([a-zA-Z0-9]+(-[a-zA-Z0-9]+)*\.)+[a-zA-Z]{2,}$")?;
-    let mut domain_regex: Vec<Regex> = Vec::new();
-    for domain in allow_domains.iter().filter(|domain| re.is_match(domain)) {
-        let domain = domain.replace('*', r"([a-zA-Z0-9]+(-[a-zA-Z0-9]+)*)");
-        let domain = domain.replace('.', r"\.");
-        if let Ok(re) = Regex::new(&format!("^{}$", domain)) {
-            domain_regex.push(re)
-        };
+    if !re.is_match(domain) {
+        return Ok(None);
     }
 
-    Ok(domain_regex.iter().any(|re| re.is_match(domain)))
+    let domain = domain.replace('*', r"([a-zA-Z0-9]+(?:-[a-zA-Z0-9]+)*)");
+    let domain = domain.replace('.', r"\.");
+    Ok(Regex::new(&format!("^{}$", domain)).ok())
+}
+
+/// Matches `host` against a regex from [`domain_regex`] and returns the lowercased labels
+/// captured by the wildcards. Returns `None` when the host does not match or a label is not a
+/// valid DNS label, so that captures are safe to substitute into bucket names and key prefixes.
+pub fn domain_captures(re: &Regex, host: &str) -> Option<Vec<String>> {
+    let captures = re.captures(host)?;
+    captures
+        .iter()
+        .skip(1)
+        .map(|label| {
+            let label = label?.as_str();
+            let valid = (1..=MAX_LABEL_LENGTH).contains(&label.len())
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+                && !label.starts_with('-')
+                && !label.ends_with('-');
+            valid.then(|| label.to_ascii_lowercase())
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::host_mapping::HostMappings;
+    use crate::routing_rules::RoutingRules;
+    use crate::site::SiteSettings;
     use test_case::test_case;
 
+    #[test_case(Some("foo.example.com"), "/index.html", Some("foo.example.com"); "host header")]
+    #[test_case(Some("foo.example.com:8000"), "/index.html", Some("foo.example.com"); "host header with port")]
+    #[test_case(Some(""), "/index.html", None; "empty host header")]
+    #[test_case(Some("foo.example.com"), "https://bar.example.com/index.html", Some("foo.example.com"); "host header before authority")]
+    #[test_case(None, "https://foo.example.com:8443/index.html", Some("foo.example.com"); "authority")]
+    #[test_case(None, "/index.html", None; "no host")]
+    fn test_request_host(header: Option<&str>, uri: &str, expected: Option<&str>) {
+        let mut builder = Request::builder().uri(uri);
+        if let Some(header) = header {
+            builder = builder.header(HOST, header);
+        }
+        let req = builder.body(()).unwrap();
+        assert_eq!(request_host(&req), expected);
+    }
+
+    #[test_case("/docs/a%20b.txt", Some("/docs/a b.txt"); "encoded space")]
+    #[test_case("/%E6%97%A5%E6%9C%AC.html", Some("/日本.html"); "encoded utf-8")]
+    #[test_case("/100%25.txt", Some("/100%.txt"); "encoded percent")]
+    #[test_case("/%2541.txt", Some("/%41.txt"); "decoded once")]
+    #[test_case("/%FF.txt", None; "invalid utf-8")]
+    fn test_decode_path(path: &str, expected: Option<&str>) {
+        assert_eq!(decode_path(path).as_deref(), expected);
+    }
+
+    #[test_case("foo.example.com:80", "/a/b.html?x=1", 443, Some("https://foo.example.com/a/b.html?x=1"); "default port")]
+    #[test_case("foo.example.com", "/", 8443, Some("https://foo.example.com:8443/"); "custom port")]
+    #[test_case("", "/", 443, None; "no host")]
+    fn test_https_location(host: &str, uri: &str, https_port: u16, expected: Option<&str>) {
+        let req = Request::builder()
+            .uri(uri)
+            .header(HOST, host)
+            .body(())
+            .unwrap();
+        assert_eq!(https_location(&req, https_port).as_deref(), expected);
+    }
+
+    #[test_case("foo.example.com", StatusCode::PERMANENT_REDIRECT; "served host")]
+    #[test_case("foo.example.net", StatusCode::FORBIDDEN; "unknown host")]
+    #[test_case("", StatusCode::BAD_REQUEST; "no host")]
+    #[tokio::test]
+    async fn test_redirect_route(host: &str, expected: StatusCode) {
+        let sites = Sites::new(
+            HostMappings::new(Vec::new(), &["*.example.com".to_string()]),
+            SiteSettings::default(),
+            Vec::new(),
+            Vec::new(),
+            RoutingRules::default(),
+        );
+        let req = Request::builder()
+            .uri("/index.html")
+            .header(HOST, host)
+            .body(())
+            .unwrap();
+
+        let resp = redirect_route(req, sites, 443).await.unwrap();
+        assert_eq!(resp.status(), expected);
+    }
+
     #[test_case(vec!["foo.example.com"], "foo.example.com"; "exact match")]
     #[test_case(vec!["*.example.com"], "foo.example.com"; "wildcard match")]
     #[test_case(vec!["*.bar.example.com"], "foo.bar.example.com"; "wildcard match with subdomain")]
@@ -118,7 +348,7 @@ mod tests {
             .iter()
             .map(|domain| domain.to_string())
             .collect::<Vec<String>>();
-        assert!(is_allow_domain(allow_domains, domain).unwrap());
+        assert!(is_allow_domain(&domain_regexes(&allow_domains), domain));
     }
 
     #[test_case(vec!["foo.example.com"], "bar.example.com"; "exact match")]
@@ -130,7 +360,7 @@ mod tests {
             .iter()
             .map(|domain| domain.to_string())
             .collect::<Vec<String>>();
-        assert!(!is_allow_domain(allow_domains, domain).unwrap());
+        assert!(!is_allow_domain(&domain_regexes(&allow_domains), domain));
     }
 
     #[test_case(vec!["*example.com"], "foo.example.com"; "invalid wildcard match")]
@@ -141,6 +371,31 @@ mod tests {
             .iter()
             .map(|domain| domain.to_string())
             .collect::<Vec<String>>();
-        assert!(!is_allow_domain(allow_domains, domain).unwrap());
+        assert!(!is_allow_domain(&domain_regexes(&allow_domains), domain));
+    }
+
+    #[test_case("text/html,application/xhtml+xml,*/*;q=0.8", "/settings/profile", true; "navigation")]
+    #[test_case("text/html", "/settings/profile.html", true; "html with extension")]
+    #[test_case("*/*", "/settings/profile", true; "no extension")]
+    #[test_case("*/*", "/assets/app.js", false; "missing asset")]
+    #[test_case("", "/assets/app.js", false; "no accept")]
+    fn test_is_spa_route(accept: &'static str, path: &str, expected: bool) {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, accept.parse().unwrap());
+        assert_eq!(is_spa_route(&headers, path), expected);
+    }
+
+    #[test_case("*.preview.example.com", "pr-123.preview.example.com", Some(vec!["pr-123"]); "wildcard")]
+    #[test_case("*.preview.example.com", "PR-123.preview.example.com", Some(vec!["pr-123"]); "lowercased")]
+    #[test_case("foo.example.com", "foo.example.com", Some(vec![]); "no wildcard")]
+    #[test_case("*.preview.example.com", "a.b.preview.example.com", None; "nested subdomain")]
+    #[test_case("*.preview.example.com", "preview.example.com", None; "missing label")]
+    #[test_case("*.example.com", &format!("{}.example.com", "a".repeat(64)), None; "label too long")]
+    fn test_domain_captures(domain: &str, host: &str, expected: Option<Vec<&str>>) {
+        let re = domain_regex(domain).unwrap().unwrap();
+        assert_eq!(
+            domain_captures(&re, host),
+            expected.map(|labels| labels.iter().map(|label| label.to_string()).collect())
+        );
     }
 }
@@ -0,0 +1,381 @@
+use crate::{config, listing, router};
+use hyper::StatusCode;
+use percent_encoding::utf8_percent_encode;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use std::sync::Arc;
+
+/// Redirect rules per host, modelled on the `RoutingRules` of S3 static website hosting.
+/// The rules of the first entry whose host pattern matches the request host apply.
+#[derive(Debug, Clone, Default)]
+pub struct RoutingRules(Arc<Vec<(Regex, Vec<RoutingRule>)>>);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct HostRoutingRules {
+    /// A domain as accepted by [`domain_regex`](crate::router::domain_regex).
+    host: String,
+    routing_rules: Vec<RoutingRule>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RoutingRule {
+    #[serde(default)]
+    condition: Condition,
+    redirect: Redirect,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Condition {
+    key_prefix_equals: Option<String>,
+    #[serde(default, deserialize_with = "status_code")]
+    http_error_code_returned_equals: Option<u16>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Redirect {
+    host_name: Option<String>,
+    protocol: Option<Protocol>,
+    replace_key_prefix_with: Option<String>,
+    replace_key_with: Option<String>,
+    #[serde(default, deserialize_with = "status_code")]
+    http_redirect_code: Option<u16>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Http,
+    Https,
+}
+
+impl Protocol {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Protocol::Http => "http",
+            Protocol::Https => "https",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutingRedirect {
+    pub status: StatusCode,
+    pub location: String,
+}
+
+impl RoutingRules {
+    /// Parses rules given as JSON, e.g.
+    /// `[{"Host": "www.example.com", "RoutingRules": [{"Condition": {...}, "Redirect": {...}}]}]`.
+    #[cfg(test)]
+    fn from_json(value: &str) -> Result<Self, String> {
+        Self::new(serde_json::from_str(value).map_err(|e| e.to_string())?)
+    }
+
+    /// Validates the rules and compiles the host patterns once.
+    fn new(hosts: Vec<HostRoutingRules>) -> Result<Self, String> {
+        hosts
+            .into_iter()
+            .map(|host| {
+                for rule in &host.routing_rules {
+                    rule.validate()?;
+                }
+                let regex = router::domain_regex(&host.host)
+                    .ok()
+                    .flatten()
+                    .ok_or_else(|| format!("invalid host: {}", host.host))?;
+                Ok((regex, host.routing_rules))
+            })
+            .collect::<Result<Vec<_>, String>>()
+            .map(|hosts| Self(Arc::new(hosts)))
+    }
+
+    pub fn for_host(&self, host: &str) -> &[RoutingRule] {
+        self.0
+            .iter()
+            .find(|(regex, _)| regex.is_match(host))
+            .map(|(_, rules)| rules.as_slice())
+            .unwrap_or_default()
+    }
+}
+
+/// Deserializes [`RoutingRules`] from a JSON string or from structured data in a file.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<RoutingRules, D::Error> {
+    let hosts = config::deserialize_nested(deserializer)?;
+    RoutingRules::new(hosts).map_err(serde::de::Error::custom)
+}
+
+/// Returns the redirect of the first matching rule. Without `error_code` only rules without
+/// `HttpErrorCodeReturnedEquals` are evaluated, i.e. before the object is fetched; with it only
+/// the rules for that status are evaluated, i.e. after S3 returned an error.
+pub fn evaluate(
+    rules: &[RoutingRule],
+    host: &str,
+    key: &str,
+    error_code: Option<StatusCode>,
+) -> Option<RoutingRedirect> {
+    let error_code = error_code.map(|status| status.as_u16());
+    rules
+        .iter()
+        .find(|rule| rule.matches(key, error_code))
+        .map(|rule| rule.redirect(host, key))
+}
+
+impl RoutingRule {
+    fn validate(&self) -> Result<(), String> {
+        if let Some(code) = self.condition.http_error_code_returned_equals {
+            if !(400..600).contains(&code) {
+                return Err(format!(
+                    "HttpErrorCodeReturnedEquals must be 4XX or 5XX: {}",
+                    code
+                ));
+            }
+        }
+        if let Some(code) = self.redirect.http_redirect_code {
+            if !(300..400).contains(&code) {
+                return Err(format!("HttpRedirectCode must be 3XX: {}", code));
+            }
+        }
+        if self.redirect.replace_key_prefix_with.is_some()
+            && self.redirect.replace_key_with.is_some()
+        {
+            return Err("ReplaceKeyPrefixWith and ReplaceKeyWith are exclusive".to_string());
+        }
+
+        Ok(())
+    }
+
+    fn matches(&self, key: &str, error_code: Option<u16>) -> bool {
+        self.condition.http_error_code_returned_equals == error_code
+            && self
+                .condition
+                .key_prefix_equals
+                .as_deref()
+                .is_none_or(|prefix| key.starts_with(prefix))
+    }
+
+    fn redirect(&self, host: &str, key: &str) -> RoutingRedirect {
+        let redirect = &self.redirect;
+        let key = match (
+            &redirect.replace_key_with,
+            &redirect.replace_key_prefix_with,
+        ) {
+            (Some(replace_key), _) => replace_key.to_string(),
+            (None, Some(replace_prefix)) => {
+                let prefix = self
+                    .condition
+                    .key_prefix_equals
+                    .as_deref()
+                    .unwrap_or_default();
+                format!(
+                    "{}{}",
+                    replace_prefix,
+                    key.strip_prefix(prefix).unwrap_or(key)
+                )
+            }
+            (None, None) => key.to_string(),
+        };
+        // The key was decoded from the request path, so it is encoded again for the location.
+        let key = utf8_percent_encode(&key, listing::PATH);
+
+        // Without an explicit protocol the scheme of the request is kept by a scheme-relative
+        // location, since the gateway may sit behind a TLS terminating proxy.
+        let location = match (redirect.protocol, &redirect.host_name) {
+            (Some(protocol), host_name) => format!(
+                "{}://{}/{}",
+                protocol.as_str(),
+                host_name.as_deref().unwrap_or(host),
+                key
+            ),
+            (None, Some(host_name)) => format!("//{}/{}", host_name, key),
+            (None, None) => format!("/{}", key),
+        };
+
+        RoutingRedirect {
+            status: redirect
+                .http_redirect_code
+                .and_then(|code| StatusCode::from_u16(code).ok())
+                .unwrap_or(StatusCode::MOVED_PERMANENTLY),
+            location,
+        }
+    }
+}
+
+/// S3 writes status codes as strings, e.g. `"404"`; numbers are accepted as well.
+fn status_code<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u16>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Code {
+        Number(u16),
+        Text(String),
+    }
+
+    match Option::<Code>::deserialize(deserializer)? {
+        Some(Code::Number(code)) => Ok(Some(code)),
+        Some(Code::Text(code)) => code.parse().map(Some).map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn rules(json: &str) -> RoutingRules {
+        RoutingRules::from_json(&format!(
+            r#"[{{"Host": "www.example.com", "RoutingRules": {}}}]"#,
+            json
+        ))
+        .unwrap()
+    }
+
+    fn redirect(
+        rules: &RoutingRules,
+        key: &str,
+        error_code: Option<StatusCode>,
+    ) -> Option<(u16, String)> {
+        evaluate(
+            rules.for_host("www.example.com"),
+            "www.example.com",
+            key,
+            error_code,
+        )
+        .map(|redirect| (redirect.status.as_u16(), redirect.location))
+    }
+
+    #[test_case("/docs/a%20b.html", "/documents/a%20b.html"; "space")]
+    #[test_case("/docs/a%3Fb.html", "/documents/a%3Fb.html"; "question mark")]
+    #[test_case("/docs/a%23b.html", "/documents/a%23b.html"; "hash")]
+    #[test_case("/docs/a%0Ab.html", "/documents/a%0Ab.html"; "newline")]
+    #[test_case("/docs/%E6%97%A5.html", "/documents/%E6%97%A5.html"; "non-ascii")]
+    fn test_location_encodes_key(path: &str, expected: &str) {
+        let rules = rules(
+            r#"[{"Condition": {"KeyPrefixEquals": "docs/"},
+                 "Redirect": {"ReplaceKeyPrefixWith": "documents/"}}]"#,
+        );
+
+        let path = router::decode_path(path).unwrap();
+        let (_, location) = redirect(&rules, path.trim_start_matches('/'), None).unwrap();
+        assert_eq!(location, expected);
+        assert!(hyper::header::HeaderValue::from_str(&location).is_ok());
+    }
+
+    // Example 1: Redirect after renaming a key prefix.
+    #[test]
+    fn test_replace_key_prefix() {
+        let rules = rules(
+            r#"[{"Condition": {"KeyPrefixEquals": "docs/"},
+                 "Redirect": {"ReplaceKeyPrefixWith": "documents/"}}]"#,
+        );
+
+        assert_eq!(
+            redirect(&rules, "docs/article1.html", None),
+            Some((301, "/documents/article1.html".to_string()))
+        );
+        assert_eq!(redirect(&rules, "documents/article1.html", None), None);
+    }
+
+    // Example 2: Redirect requests for a deleted folder to a page.
+    #[test]
+    fn test_replace_key() {
+        let rules = rules(
+            r#"[{"Condition": {"KeyPrefixEquals": "images/"},
+                 "Redirect": {"ReplaceKeyWith": "folderdeleted.html"}}]"#,
+        );
+
+        assert_eq!(
+            redirect(&rules, "images/photo1.jpg", None),
+            Some((301, "/folderdeleted.html".to_string()))
+        );
+    }
+
+    // Example 3: Redirect for an HTTP error.
+    #[test]
+    fn test_http_error_code() {
+        let rules = rules(
+            r#"[{"Condition": {"HttpErrorCodeReturnedEquals": "404"},
+                 "Redirect": {"HostName": "ec2-11-22-333-44.compute-1.amazonaws.com",
+                              "ReplaceKeyPrefixWith": "report-404/"}}]"#,
+        );
+
+        assert_eq!(redirect(&rules, "ExamplePage.html", None), None);
+        assert_eq!(
+            redirect(&rules, "ExamplePage.html", Some(StatusCode::FORBIDDEN)),
+            None
+        );
+        assert_eq!(
+            redirect(&rules, "ExamplePage.html", Some(StatusCode::NOT_FOUND)),
+            Some((
+                301,
+                "//ec2-11-22-333-44.compute-1.amazonaws.com/report-404/ExamplePage.html"
+                    .to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_protocol_and_redirect_code() {
+        let rules = rules(
+            r#"[{"Condition": {"KeyPrefixEquals": "blog/", "HttpErrorCodeReturnedEquals": 404},
+                 "Redirect": {"Protocol": "https", "HostName": "blog.example.com",
+                              "ReplaceKeyPrefixWith": "", "HttpRedirectCode": "302"}},
+                {"Redirect": {"Protocol": "https"}}]"#,
+        );
+
+        assert_eq!(
+            redirect(&rules, "blog/post.html", Some(StatusCode::NOT_FOUND)),
+            Some((302, "https://blog.example.com/post.html".to_string()))
+        );
+        assert_eq!(
+            redirect(&rules, "index.html", None),
+            Some((301, "https://www.example.com/index.html".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_for_host() {
+        let rules = RoutingRules::from_json(
+            r#"[{"Host": "www.example.com", "RoutingRules": [{"Redirect": {"ReplaceKeyWith": "a"}}]},
+                {"Host": "*.example.com", "RoutingRules": [{"Redirect": {"ReplaceKeyWith": "b"}}]}]"#,
+        )
+        .unwrap();
+
+        assert_eq!(rules.for_host("www.example.com").len(), 1);
+        assert_eq!(
+            evaluate(
+                rules.for_host("foo.example.com"),
+                "foo.example.com",
+                "x",
+                None
+            )
+            .map(|redirect| redirect.location),
+            Some("/b".to_string())
+        );
+        assert!(rules.for_host("example.org").is_empty());
+    }
+
+    #[test_case(r#"[{"Redirect": {"HttpRedirectCode": "200"}}]"#; "redirect code not 3xx")]
+    #[test_case(r#"[{"Condition": {"HttpErrorCodeReturnedEquals": "302"}, "Redirect": {}}]"#; "error code not 4xx or 5xx")]
+    #[test_case(r#"[{"Redirect": {"ReplaceKeyWith": "a", "ReplaceKeyPrefixWith": "b"}}]"#; "exclusive replacements")]
+    #[test_case(r#"[{"Redirect": {"Protocol": "ftp"}}]"#; "unknown protocol")]
+    #[test_case(r#"[{"Condition": {"KeyPrefixEquals": "docs/"}}]"#; "missing redirect")]
+    fn test_invalid(json: &str) {
+        assert!(RoutingRules::from_json(&format!(
+            r#"[{{"Host": "www.example.com", "RoutingRules": {}}}]"#,
+            json
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn test_invalid_host() {
+        assert!(RoutingRules::from_json(
+            r#"[{"Host": "*.*.example.com", "RoutingRules": [{"Redirect": {"ReplaceKeyWith": "a"}}]}]"#
+        )
+        .is_err());
+    }
+}
@@ -1,35 +1,309 @@
 use aws_sdk_s3::error::SdkError;
-use aws_sdk_s3::operation::get_object::GetObjectError;
+use aws_sdk_s3::operation::get_object::{GetObjectError, GetObjectOutput};
 use aws_sdk_s3::operation::head_bucket::HeadBucketError;
-use aws_sdk_s3::operation::head_object::HeadObjectError;
+use aws_sdk_s3::operation::head_object::{HeadObjectError, HeadObjectOutput};
+use aws_sdk_s3::operation::list_objects_v2::{ListObjectsV2Error, ListObjectsV2Output};
 use aws_smithy_types::byte_stream::ByteStream;
+use aws_smithy_types::date_time::Format;
+use aws_smithy_types::DateTime;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use typed_builder::TypedBuilder;
+
+#[derive(Debug, Clone, Default, TypedBuilder, Serialize, Deserialize)]
+#[builder(field_defaults(default, setter(strip_option, into)))]
+pub struct ObjectMetadata {
+    content_length: Option<i64>,
+    content_type: Option<String>,
+    cache_control: Option<String>,
+    content_encoding: Option<String>,
+    content_disposition: Option<String>,
+    content_language: Option<String>,
+    #[serde(with = "date_time")]
+    expires: Option<DateTime>,
+    e_tag: Option<String>,
+    #[serde(with = "date_time")]
+    last_modified: Option<DateTime>,
+    website_redirect_location: Option<String>,
+    #[builder(setter(!strip_option))]
+    metadata: HashMap<String, String>,
+}
+
+impl ObjectMetadata {
+    pub fn content_length(&self) -> Option<i64> {
+        self.content_length
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    pub fn cache_control(&self) -> Option<&str> {
+        self.cache_control.as_deref()
+    }
+
+    pub fn content_encoding(&self) -> Option<&str> {
+        self.content_encoding.as_deref()
+    }
+
+    pub fn content_disposition(&self) -> Option<&str> {
+        self.content_disposition.as_deref()
+    }
+
+    pub fn content_language(&self) -> Option<&str> {
+        self.content_language.as_deref()
+    }
+
+    pub fn expires(&self) -> Option<&DateTime> {
+        self.expires.as_ref()
+    }
+
+    pub fn e_tag(&self) -> Option<&str> {
+        self.e_tag.as_deref()
+    }
+
+    pub fn last_modified(&self) -> Option<&DateTime> {
+        self.last_modified.as_ref()
+    }
+
+    /// Returns the target of `x-amz-website-redirect-location`, either a path or an absolute URL.
+    pub fn website_redirect_location(&self) -> Option<&str> {
+        self.website_redirect_location.as_deref()
+    }
+
+    pub fn with_content_length(self, content_length: i64) -> Self {
+        Self {
+            content_length: Some(content_length),
+            ..self
+        }
+    }
+
+    /// Returns the user-defined metadata value stored as `x-amz-meta-<name>`.
+    pub fn user_metadata(&self, name: &str) -> Option<&str> {
+        self.metadata
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+}
+
+/// Serializes optional timestamps as RFC 3339 strings.
+mod date_time {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<DateTime>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        value
+            .map(|d| d.fmt(Format::DateTime))
+            .transpose()
+            .map_err(serde::ser::Error::custom)?
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<DateTime>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|s| DateTime::from_str(&s, Format::DateTime))
+            .transpose()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl From<HeadObjectOutput> for ObjectMetadata {
+    fn from(output: HeadObjectOutput) -> Self {
+        Self {
+            content_length: output.content_length,
+            content_type: output.content_type,
+            cache_control: output.cache_control,
+            content_encoding: output.content_encoding,
+            content_disposition: output.content_disposition,
+            content_language: output.content_language,
+            expires: output.expires,
+            e_tag: output.e_tag,
+            last_modified: output.last_modified,
+            website_redirect_location: output.website_redirect_location,
+            metadata: output.metadata.unwrap_or_default(),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct GetObjectResult {
     body: ByteStream,
+    content_range: Option<String>,
+    metadata: ObjectMetadata,
 }
 
 impl GetObjectResult {
+    pub fn new(body: ByteStream, content_range: Option<String>, metadata: ObjectMetadata) -> Self {
+        Self {
+            body,
+            content_range,
+            metadata,
+        }
+    }
+
+    pub fn content_range(&self) -> Option<&str> {
+        self.content_range.as_deref()
+    }
+
+    pub fn metadata(&self) -> &ObjectMetadata {
+        &self.metadata
+    }
+
     pub fn body(self) -> ByteStream {
         self.body
     }
 }
 
+impl From<GetObjectOutput> for GetObjectResult {
+    fn from(output: GetObjectOutput) -> Self {
+        Self {
+            body: output.body,
+            content_range: output.content_range,
+            metadata: ObjectMetadata {
+                content_length: output.content_length,
+                content_type: output.content_type,
+                cache_control: output.cache_control,
+                content_encoding: output.content_encoding,
+                content_disposition: output.content_disposition,
+                content_language: output.content_language,
+                expires: output.expires,
+                e_tag: output.e_tag,
+                last_modified: output.last_modified,
+                website_redirect_location: output.website_redirect_location,
+                metadata: output.metadata.unwrap_or_default(),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ListedObject {
+    key: String,
+    size: i64,
+    #[serde(serialize_with = "date_time::serialize")]
+    last_modified: Option<DateTime>,
+}
+
+impl ListedObject {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn size(&self) -> i64 {
+        self.size
+    }
+
+    pub fn last_modified(&self) -> Option<&DateTime> {
+        self.last_modified.as_ref()
+    }
+}
+
+/// One page of the objects and common prefixes directly under a prefix.
+#[derive(Debug, Clone, Default)]
+pub struct ObjectListing {
+    objects: Vec<ListedObject>,
+    common_prefixes: Vec<String>,
+    next_continuation_token: Option<String>,
+}
+
+impl ObjectListing {
+    pub fn objects(&self) -> &[ListedObject] {
+        &self.objects
+    }
+
+    pub fn common_prefixes(&self) -> &[String] {
+        &self.common_prefixes
+    }
+
+    pub fn next_continuation_token(&self) -> Option<&str> {
+        self.next_continuation_token.as_deref()
+    }
+
+    /// Removes `prefix` from the keys and common prefixes, so that they are relative to it.
+    pub fn strip_prefix(self, prefix: &str) -> Self {
+        let strip = |key: String| match key.strip_prefix(prefix) {
+            Some(stripped) => stripped.to_string(),
+            None => key,
+        };
+
+        Self {
+            objects: self
+                .objects
+                .into_iter()
+                .map(|object| ListedObject {
+                    key: strip(object.key),
+                    ..object
+                })
+                .collect(),
+            common_prefixes: self.common_prefixes.into_iter().map(strip).collect(),
+            next_continuation_token: self.next_continuation_token,
+        }
+    }
+}
+
+impl From<ListObjectsV2Output> for ObjectListing {
+    fn from(output: ListObjectsV2Output) -> Self {
+        Self {
+            objects: output
+                .contents
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|object| {
+                    Some(ListedObject {
+                        key: object.key?,
+                        size: object.size.unwrap_or_default(),
+                        last_modified: object.last_modified,
+                    })
+                })
+                .collect(),
+            common_prefixes: output
+                .common_prefixes
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|prefix| prefix.prefix)
+                .collect(),
+            next_continuation_token: output.next_continuation_token,
+        }
+    }
+}
+
 #[async_trait::async_trait]
 pub trait S3 {
     async fn get_object(
         &self,
         bucket: &str,
         key: &str,
+        range: Option<String>,
     ) -> Result<GetObjectResult, SdkError<GetObjectError>>;
 
-    async fn head_object(&self, bucket: &str, key: &str) -> Result<(), SdkError<HeadObjectError>>;
+    async fn head_object(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> Result<ObjectMetadata, SdkError<HeadObjectError>>;
 
     async fn head_bucket(
         &self,
         bucket: &str,
         expected_bucket_owner: &str,
     ) -> Result<(), SdkError<HeadBucketError>>;
+
+    /// Lists the objects and common prefixes directly under `prefix`, using `/` as the delimiter.
+    async fn list_objects(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        continuation_token: Option<String>,
+    ) -> Result<ObjectListing, SdkError<ListObjectsV2Error>>;
+
+    /// Returns whether the object exists. Any error is treated as absence.
+    async fn object_exists(&self, bucket: &str, key: &str) -> bool {
+        self.head_object(bucket, key).await.is_ok()
+    }
 }
 
 #[cfg(not(feature = "__tests"))]
@@ -56,24 +330,30 @@ impl S3 for Client {
         &self,
         bucket: &str,
         key: &str,
+        range: Option<String>,
     ) -> Result<GetObjectResult, SdkError<GetObjectError>> {
         self.inner
             .get_object()
             .bucket(bucket)
             .key(key)
+            .set_range(range)
             .send()
             .await
-            .map(|output| GetObjectResult { body: output.body })
+            .map(GetObjectResult::from)
     }
 
-    async fn head_object(&self, bucket: &str, key: &str) -> Result<(), SdkError<HeadObjectError>> {
+    async fn head_object(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> Result<ObjectMetadata, SdkError<HeadObjectError>> {
         self.inner
             .head_object()
             .bucket(bucket)
             .key(key)
             .send()
             .await
-            .map(|_| ())
+            .map(ObjectMetadata::from)
     }
 
     async fn head_bucket(
@@ -89,6 +369,23 @@ impl S3 for Client {
             .await
             .map(|_| ())
     }
+
+    async fn list_objects(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        continuation_token: Option<String>,
+    ) -> Result<ObjectListing, SdkError<ListObjectsV2Error>> {
+        self.inner
+            .list_objects_v2()
+            .bucket(bucket)
+            .prefix(prefix)
+            .delimiter("/")
+            .set_continuation_token(continuation_token)
+            .send()
+            .await
+            .map(ObjectListing::from)
+    }
 }
 
 #[cfg(feature = "__tests")]
@@ -122,24 +419,30 @@ impl S3 for Mock {
         &self,
         bucket: &str,
         key: &str,
+        range: Option<String>,
     ) -> Result<GetObjectResult, SdkError<GetObjectError>> {
         self.inner_client
             .get_object()
             .bucket(bucket)
             .key(key)
+            .set_range(range)
             .send()
             .await
-            .map(|output| GetObjectResult { body: output.body })
+            .map(GetObjectResult::from)
     }
 
-    async fn head_object(&self, bucket: &str, key: &str) -> Result<(), SdkError<HeadObjectError>> {
+    async fn head_object(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> Result<ObjectMetadata, SdkError<HeadObjectError>> {
         self.inner_client
             .head_object()
             .bucket(bucket)
             .key(key)
             .send()
             .await
-            .map(|_| ())
+            .map(ObjectMetadata::from)
     }
 
     async fn head_bucket(
@@ -171,4 +474,259 @@ impl S3 for Mock {
             ))
         }
     }
+
+    async fn list_objects(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        continuation_token: Option<String>,
+    ) -> Result<ObjectListing, SdkError<ListObjectsV2Error>> {
+        self.inner_client
+            .list_objects_v2()
+            .bucket(bucket)
+            .prefix(prefix)
+            .delimiter("/")
+            .set_continuation_token(continuation_token)
+            .send()
+            .await
+            .map(ObjectListing::from)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::range;
+    use aws_sdk_s3::types::error::{NoSuchKey, NotFound};
+    use aws_smithy_runtime_api::http::{Response, StatusCode};
+    use aws_smithy_types::body::SdkBody;
+    use aws_smithy_types::error::ErrorMetadata;
+    use bytes::Bytes;
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// An in-memory bucket for unit tests. Every bucket holds the same objects and missing keys
+    /// fail with `404` like S3. `head_bucket` succeeds unless a status was set for the bucket.
+    #[derive(Debug, Clone, Default)]
+    pub(crate) struct Objects {
+        objects: BTreeMap<String, (Bytes, ObjectMetadata)>,
+        bucket_errors: BTreeMap<String, u16>,
+    }
+
+    impl Objects {
+        pub(crate) fn with(
+            mut self,
+            key: &str,
+            body: &'static str,
+            metadata: ObjectMetadata,
+        ) -> Self {
+            let metadata = metadata.with_content_length(body.len() as i64);
+            self.objects.insert(
+                key.to_string(),
+                (Bytes::from_static(body.as_bytes()), metadata),
+            );
+            self
+        }
+
+        pub(crate) fn with_bucket_error(mut self, bucket: &str, status: u16) -> Self {
+            self.bucket_errors.insert(bucket.to_string(), status);
+            self
+        }
+    }
+
+    /// Requests that reached a [`Counting`] client, per operation.
+    #[derive(Debug, Default)]
+    pub(crate) struct Calls {
+        pub(crate) get_object: AtomicUsize,
+        pub(crate) head_object: AtomicUsize,
+        pub(crate) head_bucket: AtomicUsize,
+    }
+
+    impl Calls {
+        pub(crate) fn get_object(&self) -> usize {
+            self.get_object.load(Ordering::SeqCst)
+        }
+
+        pub(crate) fn head_object(&self) -> usize {
+            self.head_object.load(Ordering::SeqCst)
+        }
+
+        pub(crate) fn head_bucket(&self) -> usize {
+            self.head_bucket.load(Ordering::SeqCst)
+        }
+    }
+
+    /// Serves [`Objects`] and counts the requests that reach it, answering each after `delay` so
+    /// tests can overlap concurrent requests. Clones share their [`Calls`].
+    #[derive(Debug, Clone, Default)]
+    pub(crate) struct Counting {
+        pub(crate) objects: Objects,
+        pub(crate) calls: Arc<Calls>,
+        pub(crate) delay: Duration,
+    }
+
+    impl Counting {
+        pub(crate) fn new(objects: Objects) -> Self {
+            Self {
+                objects,
+                ..Default::default()
+            }
+        }
+
+        pub(crate) fn with_delay(self, delay: Duration) -> Self {
+            Self { delay, ..self }
+        }
+
+        async fn call(&self, counter: &AtomicUsize) {
+            counter.fetch_add(1, Ordering::SeqCst);
+            if !self.delay.is_zero() {
+                tokio::time::sleep(self.delay).await;
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl S3 for Counting {
+        async fn get_object(
+            &self,
+            bucket: &str,
+            key: &str,
+            range: Option<String>,
+        ) -> Result<GetObjectResult, SdkError<GetObjectError>> {
+            self.call(&self.calls.get_object).await;
+            self.objects.get_object(bucket, key, range).await
+        }
+
+        async fn head_object(
+            &self,
+            bucket: &str,
+            key: &str,
+        ) -> Result<ObjectMetadata, SdkError<HeadObjectError>> {
+            self.call(&self.calls.head_object).await;
+            self.objects.head_object(bucket, key).await
+        }
+
+        async fn head_bucket(
+            &self,
+            bucket: &str,
+            expected_bucket_owner: &str,
+        ) -> Result<(), SdkError<HeadBucketError>> {
+            self.call(&self.calls.head_bucket).await;
+            self.objects
+                .head_bucket(bucket, expected_bucket_owner)
+                .await
+        }
+
+        async fn list_objects(
+            &self,
+            bucket: &str,
+            prefix: &str,
+            continuation_token: Option<String>,
+        ) -> Result<ObjectListing, SdkError<ListObjectsV2Error>> {
+            self.objects
+                .list_objects(bucket, prefix, continuation_token)
+                .await
+        }
+    }
+
+    pub(crate) fn error_response(status: u16) -> Response {
+        Response::new(StatusCode::try_from(status).unwrap(), SdkBody::empty())
+    }
+
+    #[async_trait::async_trait]
+    impl S3 for Objects {
+        async fn get_object(
+            &self,
+            _bucket: &str,
+            key: &str,
+            range: Option<String>,
+        ) -> Result<GetObjectResult, SdkError<GetObjectError>> {
+            let Some((body, metadata)) = self.objects.get(key) else {
+                return Err(SdkError::service_error(
+                    GetObjectError::NoSuchKey(NoSuchKey::builder().build()),
+                    error_response(404),
+                ));
+            };
+            let Some(range) = range else {
+                return Ok(GetObjectResult::new(
+                    ByteStream::from(body.clone()),
+                    None,
+                    metadata.clone(),
+                ));
+            };
+
+            let total = body.len() as u64;
+            let Some((start, end)) = range::resolve_single(&range, total) else {
+                return Err(SdkError::service_error(
+                    GetObjectError::generic(ErrorMetadata::builder().code("InvalidRange").build()),
+                    error_response(416),
+                ));
+            };
+            let body = body.slice(start as usize..=end as usize);
+            let metadata = metadata.clone().with_content_length(body.len() as i64);
+            Ok(GetObjectResult::new(
+                ByteStream::from(body),
+                Some(range::content_range(start, end, total)),
+                metadata,
+            ))
+        }
+
+        async fn head_object(
+            &self,
+            _bucket: &str,
+            key: &str,
+        ) -> Result<ObjectMetadata, SdkError<HeadObjectError>> {
+            match self.objects.get(key) {
+                Some((_, metadata)) => Ok(metadata.clone()),
+                None => Err(SdkError::service_error(
+                    HeadObjectError::NotFound(NotFound::builder().build()),
+                    error_response(404),
+                )),
+            }
+        }
+
+        async fn head_bucket(
+            &self,
+            bucket: &str,
+            _expected_bucket_owner: &str,
+        ) -> Result<(), SdkError<HeadBucketError>> {
+            match self.bucket_errors.get(bucket) {
+                Some(&status) => Err(SdkError::service_error(
+                    HeadBucketError::unhandled("head bucket failed"),
+                    error_response(status),
+                )),
+                None => Ok(()),
+            }
+        }
+
+        async fn list_objects(
+            &self,
+            _bucket: &str,
+            prefix: &str,
+            _continuation_token: Option<String>,
+        ) -> Result<ObjectListing, SdkError<ListObjectsV2Error>> {
+            let mut listing = ObjectListing::default();
+            for (key, (body, metadata)) in self.objects.range(prefix.to_string()..) {
+                let Some(rest) = key.strip_prefix(prefix) else {
+                    break;
+                };
+                match rest.find('/') {
+                    Some(end) => {
+                        let common_prefix = format!("{}{}", prefix, &rest[..=end]);
+                        if listing.common_prefixes.last() != Some(&common_prefix) {
+                            listing.common_prefixes.push(common_prefix);
+                        }
+                    }
+                    None => listing.objects.push(ListedObject {
+                        key: key.clone(),
+                        size: body.len() as i64,
+                        last_modified: metadata.last_modified,
+                    }),
+                }
+            }
+            Ok(listing)
+        }
+    }
 }
@@ -1,3 +1,12 @@
+use crate::cache::{CacheConfig, CachedS3};
+use crate::coalesce::CoalescingS3;
+use crate::compression::{CompressionPolicy, CompressionService};
+use crate::disk_cache::{DiskCacheConfig, DiskCachedS3};
+use crate::encoding::Encoding;
+use crate::negative_cache::{NegativeCacheConfig, NegativeCachedS3};
+use crate::ownership::OwnershipCache;
+use crate::reload::ActiveConfig;
+use crate::response::{MetadataPolicy, ResponseBody};
 use crate::{s3, service};
 use aws_config::BehaviorVersion;
 #[cfg(feature = "__tests")]
@@ -7,25 +16,45 @@ use aws_credential_types::Credentials;
 use aws_sdk_sts::operation::get_caller_identity::GetCallerIdentityError;
 #[cfg(feature = "__tests")]
 use aws_types::sdk_config::SharedCredentialsProvider;
-use bytes::Bytes;
-use http_body_util::Full;
 use hyper::body::Incoming;
-use hyper::server::conn::http1;
 use hyper::service::Service;
 use hyper::{Request, Response};
-use hyper_util::rt::TokioIo;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::GracefulShutdown;
 use std::net::SocketAddr;
+use std::time::Duration;
 use tokio::net::TcpListener;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
 use typed_builder::TypedBuilder;
 
+/// The management and redirect servers only answer short requests, so their connections are
+/// idle by the time they stop.
+const IDLE_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+/// Clients that do not complete the TLS handshake in time are disconnected.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Debug, thiserror::Error)]
 pub enum ServerError {
     #[error("failed to bind to address: {0}")]
     Bind(std::io::Error),
     #[error("failed to accept connection: {0}")]
     Accept(std::io::Error),
+    #[error("failed to open disk cache: {0}")]
+    DiskCache(std::io::Error),
     #[error("failed to get self account id: {0}")]
-    GetSelfAccountId(#[from] aws_sdk_sts::error::SdkError<GetCallerIdentityError>),
+    GetSelfAccountId(#[from] Box<aws_sdk_sts::error::SdkError<GetCallerIdentityError>>),
+}
+
+/// Limits of HTTP/2 connections, negotiated by TLS ALPN or sent in cleartext (h2c).
+/// `None` keeps the default of hyper.
+#[derive(Debug, Clone, Default)]
+pub struct Http2Config {
+    pub max_concurrent_streams: Option<u32>,
+    pub initial_stream_window_size: Option<u32>,
+    pub initial_connection_window_size: Option<u32>,
 }
 
 #[derive(TypedBuilder)]
@@ -34,21 +63,53 @@ pub enum ServerError {
 )]
 pub struct GatewayServer {
     addr: SocketAddr,
-    allow_domains: Vec<String>,
+    config: ActiveConfig,
     #[builder(default)]
-    root_object: Option<String>,
+    metadata_policy: MetadataPolicy,
     #[builder(default)]
-    subdir_root_object: Option<String>,
+    precompressed_encodings: Vec<Encoding>,
+    compression_policy: CompressionPolicy,
+    cache_config: CacheConfig,
     #[builder(default)]
-    no_such_key_redirect_object: Option<String>,
+    disk_cache_config: Option<DiskCacheConfig>,
     #[builder(default)]
-    allow_cross_account: bool,
+    coalesce_max_object_size: u64,
+    ownership_cache: OwnershipCache,
+    negative_cache_config: NegativeCacheConfig,
+    #[builder(default)]
+    http2_config: Http2Config,
+    /// Terminates TLS on the listener when set.
+    #[builder(default)]
+    tls: Option<TlsAcceptor>,
+    /// Stops accepting connections and drains the open ones when cancelled.
+    shutdown: CancellationToken,
+    shutdown_timeout: Duration,
 }
 
-impl<T, U> GatewayServerBuilder<((SocketAddr,), (Vec<String>,), T, T, T, U)>
+impl<V, W, X, Y, Z, T>
+    GatewayServerBuilder<(
+        (SocketAddr,),
+        (ActiveConfig,),
+        V,
+        W,
+        (CompressionPolicy,),
+        (CacheConfig,),
+        X,
+        Y,
+        (OwnershipCache,),
+        (NegativeCacheConfig,),
+        Z,
+        T,
+        (CancellationToken,),
+        (Duration,),
+    )>
 where
-    T: typed_builder::Optional<Option<String>>,
-    U: typed_builder::Optional<bool>,
+    V: typed_builder::Optional<MetadataPolicy>,
+    W: typed_builder::Optional<Vec<Encoding>>,
+    X: typed_builder::Optional<Option<DiskCacheConfig>>,
+    Y: typed_builder::Optional<u64>,
+    Z: typed_builder::Optional<Http2Config>,
+    T: typed_builder::Optional<Option<TlsAcceptor>>,
 {
     pub async fn build(self) -> Result<(), ServerError> {
         let input = self.__build();
@@ -73,9 +134,13 @@ where
             .behavior_version(BehaviorVersion::latest())
             .build();
 
-        let self_account_id = if !input.allow_cross_account {
+        let self_account_id = if input.config.load().sites.checks_ownership() {
             let sts_client = aws_sdk_sts::Client::from_conf(aws_sdk_sts::Config::from(&aws_config));
-            let resp = sts_client.get_caller_identity().send().await?;
+            let resp = sts_client
+                .get_caller_identity()
+                .send()
+                .await
+                .map_err(Box::new)?;
             resp.account
         } else {
             None
@@ -98,15 +163,30 @@ where
             )
         };
 
+        let s3_client = DiskCachedS3::new(s3_client, input.disk_cache_config)
+            .await
+            .map_err(ServerError::DiskCache)?;
+        let s3_client = CoalescingS3::new(s3_client, input.coalesce_max_object_size);
+        let s3_client = CachedS3::new(s3_client, input.cache_config);
+        let s3_client = NegativeCachedS3::new(s3_client, input.negative_cache_config);
+
         let svc = service::GatewayService::builder()
             .s3_client(s3_client)
-            .allow_domains(input.allow_domains)
-            .root_object(input.root_object)
-            .subdir_root_object(input.subdir_root_object)
-            .no_such_key_redirect_object(input.no_such_key_redirect_object)
+            .config(input.config)
             .self_account_id(self_account_id)
+            .ownership_cache(input.ownership_cache)
+            .metadata_policy(input.metadata_policy)
+            .precompressed_encodings(input.precompressed_encodings)
             .build();
-        serve(listener, svc).await
+        serve(
+            listener,
+            CompressionService::new(svc, input.compression_policy),
+            &input.http2_config,
+            input.tls,
+            input.shutdown,
+            input.shutdown_timeout,
+        )
+        .await
     }
 }
 
@@ -116,9 +196,60 @@ where
 )]
 pub struct ManagementServer {
     addr: SocketAddr,
+    ownership_cache: OwnershipCache,
+    config: ActiveConfig,
+    /// Reported as not ready once cancelled.
+    draining: CancellationToken,
+    /// Stops the server when cancelled, after the gateway has drained.
+    shutdown: CancellationToken,
+}
+
+impl
+    ManagementServerBuilder<(
+        (SocketAddr,),
+        (OwnershipCache,),
+        (ActiveConfig,),
+        (CancellationToken,),
+        (CancellationToken,),
+    )>
+{
+    pub async fn build(self) -> Result<(), ServerError> {
+        let input = self.__build();
+
+        let listener = TcpListener::bind(input.addr)
+            .await
+            .map_err(ServerError::Bind)?;
+
+        let svc = service::ManagementService::builder()
+            .ownership_cache(input.ownership_cache)
+            .config(input.config)
+            .draining(input.draining)
+            .build();
+        serve(
+            listener,
+            svc,
+            &Http2Config::default(),
+            None,
+            input.shutdown,
+            IDLE_SHUTDOWN_TIMEOUT,
+        )
+        .await
+    }
+}
+
+/// Redirects plain HTTP requests to HTTPS on the gateway.
+#[derive(TypedBuilder)]
+#[builder(
+    build_method(vis="", name=__build)
+)]
+pub struct RedirectServer {
+    addr: SocketAddr,
+    config: ActiveConfig,
+    https_port: u16,
+    shutdown: CancellationToken,
 }
 
-impl ManagementServerBuilder<((SocketAddr,),)> {
+impl RedirectServerBuilder<((SocketAddr,), (ActiveConfig,), (u16,), (CancellationToken,))> {
     pub async fn build(self) -> Result<(), ServerError> {
         let input = self.__build();
 
@@ -126,39 +257,141 @@ impl ManagementServerBuilder<((SocketAddr,),)> {
             .await
             .map_err(ServerError::Bind)?;
 
-        let svc = service::ManagementService;
-        serve(listener, svc).await
+        let svc = service::RedirectService::builder()
+            .config(input.config)
+            .https_port(input.https_port)
+            .build();
+        serve(
+            listener,
+            svc,
+            &Http2Config::default(),
+            None,
+            input.shutdown,
+            IDLE_SHUTDOWN_TIMEOUT,
+        )
+        .await
     }
 }
 
-async fn serve<S>(listener: TcpListener, svc: S) -> Result<(), ServerError>
+/// Resolves on the first SIGTERM or SIGINT.
+pub async fn shutdown_signal() -> std::io::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        _ = terminate.recv() => tracing::info!("received SIGTERM, shutting down"),
+        _ = interrupt.recv() => tracing::info!("received SIGINT, shutting down"),
+    }
+
+    Ok(())
+}
+
+/// Serves HTTP/1 and HTTP/2 connections, over TLS when `tls` is set, until `shutdown` is
+/// cancelled, then waits up to `shutdown_timeout` for the open connections to finish their
+/// in-flight requests.
+async fn serve<S>(
+    listener: TcpListener,
+    svc: S,
+    http2_config: &Http2Config,
+    tls: Option<TlsAcceptor>,
+    shutdown: CancellationToken,
+    shutdown_timeout: Duration,
+) -> Result<(), ServerError>
 where
-    S: Service<Request<Incoming>, Response = Response<Full<Bytes>>> + Clone + Send + Sync + 'static,
+    S: Service<Request<Incoming>, Response = Response<ResponseBody>>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
     S::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
     S::Future: Send,
 {
+    let builder = connection_builder(http2_config);
+    let graceful = GracefulShutdown::new();
     loop {
-        let (stream, _) = listener.accept().await.map_err(ServerError::Accept)?;
-        let io = TokioIo::new(stream);
+        let stream = tokio::select! {
+            accepted = listener.accept() => accepted.map_err(ServerError::Accept)?.0,
+            _ = shutdown.cancelled() => break,
+        };
         let svc = svc.clone();
+        let builder = builder.clone();
+        let tls = tls.clone();
+        let watcher = graceful.watcher();
 
         tokio::spawn(async move {
-            if let Err(e) = http1::Builder::new().serve_connection(io, svc).await {
-                if e.is_closed()
-                    || e.is_parse()
-                    || e.is_parse_too_large()
-                    || e.is_parse_status()
-                    || e.is_user()
-                    || e.is_canceled()
-                    || e.is_incomplete_message()
-                    || e.is_body_write_aborted()
-                    || e.is_timeout()
-                {
-                    tracing::error!("failed to serve connection: {:?}", e);
-                } else {
-                    tracing::warn!("failed to serve connection: {:?}", e);
+            let result = match tls {
+                Some(tls) => {
+                    let stream =
+                        match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, tls.accept(stream)).await
+                        {
+                            Ok(Ok(stream)) => stream,
+                            Ok(Err(e)) => {
+                                tracing::debug!("failed to complete TLS handshake: {:?}", e);
+                                return;
+                            }
+                            Err(_) => {
+                                tracing::debug!("timed out waiting for TLS handshake");
+                                return;
+                            }
+                        };
+                    let io = TokioIo::new(stream);
+                    watcher
+                        .watch(builder.serve_connection(io, svc).into_owned())
+                        .await
+                }
+                None => {
+                    let io = TokioIo::new(stream);
+                    watcher
+                        .watch(builder.serve_connection(io, svc).into_owned())
+                        .await
+                }
+            };
+
+            if let Err(e) = result {
+                match e.downcast_ref::<hyper::Error>() {
+                    Some(e)
+                        if e.is_closed()
+                            || e.is_parse()
+                            || e.is_parse_too_large()
+                            || e.is_parse_status()
+                            || e.is_user()
+                            || e.is_canceled()
+                            || e.is_incomplete_message()
+                            || e.is_body_write_aborted()
+                            || e.is_timeout() =>
+                    {
+                        tracing::error!("failed to serve connection: {:?}", e);
+                    }
+                    _ => tracing::warn!("failed to serve connection: {:?}", e),
                 }
             }
         });
     }
+
+    drop(listener);
+    if tokio::time::timeout(shutdown_timeout, graceful.shutdown())
+        .await
+        .is_err()
+    {
+        tracing::warn!(
+            "closing connections still open after {}s",
+            shutdown_timeout.as_secs()
+        );
+    }
+
+    Ok(())
+}
+
+/// Detects the protocol of each connection: HTTP/2 by its connection preface, HTTP/1 otherwise.
+fn connection_builder(http2_config: &Http2Config) -> auto::Builder<TokioExecutor> {
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    let mut http2 = builder.http2();
+    // Unlike the window sizes, `None` would lift the default limit of concurrent streams.
+    if let Some(max) = http2_config.max_concurrent_streams {
+        http2.max_concurrent_streams(max);
+    }
+    http2
+        .initial_stream_window_size(http2_config.initial_stream_window_size)
+        .initial_connection_window_size(http2_config.initial_connection_window_size);
+
+    builder
 }
@@ -1,12 +1,15 @@
+use crate::encoding::Encoding;
+use crate::ownership::OwnershipCache;
+use crate::reload::ActiveConfig;
+use crate::response::{MetadataPolicy, ResponseBody};
 use crate::router;
 use crate::s3::S3;
-use bytes::Bytes;
-use http_body_util::Full;
 use hyper::body::Incoming;
 use hyper::service::Service;
 use hyper::{Request, Response};
 use std::future::Future;
 use std::pin::Pin;
+use tokio_util::sync::CancellationToken;
 use typed_builder::TypedBuilder;
 
 #[derive(Debug, thiserror::Error)]
@@ -18,38 +21,39 @@ pub enum ServiceError {
 #[derive(Debug, Clone, TypedBuilder)]
 pub struct GatewayService<T> {
     s3_client: T,
-    allow_domains: Vec<String>,
-    root_object: Option<String>,
-    subdir_root_object: Option<String>,
-    no_such_key_redirect_object: Option<String>,
+    config: ActiveConfig,
     self_account_id: Option<String>,
+    ownership_cache: OwnershipCache,
+    metadata_policy: MetadataPolicy,
+    precompressed_encodings: Vec<Encoding>,
 }
 
 impl<T> Service<Request<Incoming>> for GatewayService<T>
 where
     T: S3 + Clone + Send + Sync + 'static,
 {
-    type Response = Response<Full<Bytes>>;
+    type Response = Response<ResponseBody>;
     type Error = ServiceError;
     type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
 
     fn call(&self, req: Request<Incoming>) -> Self::Future {
         let s3_client = self.s3_client.clone();
-        let allow_domains = self.allow_domains.clone();
-        let default_root = self.root_object.clone();
-        let default_subdir_root = self.subdir_root_object.clone();
-        let no_such_key_redirect = self.no_such_key_redirect_object.clone();
+        // The request is served with the routing state active when it arrived.
+        let sites = self.config.load().sites.clone();
         let self_account_id = self.self_account_id.clone();
+        let ownership_cache = self.ownership_cache.clone();
+        let metadata_policy = self.metadata_policy.clone();
+        let precompressed_encodings = self.precompressed_encodings.clone();
 
         Box::pin(async move {
             router::gateway_route(
                 req,
                 s3_client,
-                allow_domains,
-                default_root,
-                default_subdir_root,
-                no_such_key_redirect,
+                sites,
                 self_account_id,
+                ownership_cache,
+                metadata_policy,
+                precompressed_encodings,
             )
             .await
             .map_err(ServiceError::Router)
@@ -57,17 +61,48 @@ where
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct ManagementService;
+#[derive(Debug, Clone, TypedBuilder)]
+pub struct ManagementService {
+    ownership_cache: OwnershipCache,
+    config: ActiveConfig,
+    draining: CancellationToken,
+}
 
 impl Service<Request<Incoming>> for ManagementService {
-    type Response = Response<Full<Bytes>>;
+    type Response = Response<ResponseBody>;
+    type Error = ServiceError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn call(&self, req: Request<Incoming>) -> Self::Future {
+        let ownership_cache = self.ownership_cache.clone();
+        let config = self.config.clone();
+        let ready = !self.draining.is_cancelled();
+
+        Box::pin(async move {
+            router::management_route(req, ownership_cache, config, ready)
+                .await
+                .map_err(ServiceError::Router)
+        })
+    }
+}
+
+#[derive(Debug, Clone, TypedBuilder)]
+pub struct RedirectService {
+    config: ActiveConfig,
+    https_port: u16,
+}
+
+impl Service<Request<Incoming>> for RedirectService {
+    type Response = Response<ResponseBody>;
     type Error = ServiceError;
     type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
 
     fn call(&self, req: Request<Incoming>) -> Self::Future {
+        let sites = self.config.load().sites.clone();
+        let https_port = self.https_port;
+
         Box::pin(async move {
-            router::management_route(req)
+            router::redirect_route(req, sites, https_port)
                 .await
                 .map_err(ServiceError::Router)
         })
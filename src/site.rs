@@ -0,0 +1,327 @@
+use crate::error_document::{self, ErrorDocuments};
+use crate::host_mapping::{HostMappings, Origin};
+use crate::routing_rules::{self, RoutingRedirect, RoutingRules};
+use crate::{config, router};
+use hyper::StatusCode;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Settings that can be overridden per host.
+#[derive(Debug, Clone, Default)]
+pub struct SiteSettings {
+    pub root_object: Option<String>,
+    pub subdir_root_object: Option<String>,
+    pub no_such_key_redirect_object: Option<String>,
+    pub spa_fallback_object: Option<String>,
+    /// `None` renders listings for the hosts matching the autoindex domains.
+    pub autoindex: Option<bool>,
+    pub allow_cross_account: bool,
+    pub error_documents: ErrorDocuments,
+}
+
+/// Overrides for the hosts matching `host`, as configured. Unset fields keep the global value.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HostSettingsConfig {
+    host: String,
+    root_object: Option<String>,
+    subdir_root_object: Option<String>,
+    no_such_key_redirect_object: Option<String>,
+    spa_fallback_object: Option<String>,
+    autoindex: Option<bool>,
+    allow_cross_account: Option<bool>,
+    error_documents: Option<Vec<String>>,
+    error_document_nearest_404: Option<bool>,
+}
+
+#[derive(Debug, Clone)]
+pub struct HostSettings {
+    regex: Regex,
+    config: HostSettingsConfig,
+    error_documents: Option<HashMap<u16, String>>,
+}
+
+impl HostSettings {
+    fn resolve(&self, defaults: &SiteSettings) -> SiteSettings {
+        let config = &self.config;
+        SiteSettings {
+            root_object: or_default(&config.root_object, &defaults.root_object),
+            subdir_root_object: or_default(
+                &config.subdir_root_object,
+                &defaults.subdir_root_object,
+            ),
+            no_such_key_redirect_object: or_default(
+                &config.no_such_key_redirect_object,
+                &defaults.no_such_key_redirect_object,
+            ),
+            spa_fallback_object: or_default(
+                &config.spa_fallback_object,
+                &defaults.spa_fallback_object,
+            ),
+            autoindex: config.autoindex.or(defaults.autoindex),
+            allow_cross_account: config
+                .allow_cross_account
+                .unwrap_or(defaults.allow_cross_account),
+            error_documents: defaults.error_documents.with_overrides(
+                self.error_documents.clone(),
+                config.error_document_nearest_404,
+            ),
+        }
+    }
+}
+
+/// An empty value disables a global setting for the host.
+fn or_default(value: &Option<String>, default: &Option<String>) -> Option<String> {
+    match value.as_deref() {
+        Some("") => None,
+        Some(value) => Some(value.to_string()),
+        None => default.clone(),
+    }
+}
+
+/// A host that is served, with its origin and settings.
+#[derive(Debug, Clone)]
+pub struct Site {
+    host: String,
+    origin: Origin,
+    settings: Arc<SiteSettings>,
+    autoindex: bool,
+    routing_rules: RoutingRules,
+}
+
+impl Site {
+    pub fn origin(&self) -> &Origin {
+        &self.origin
+    }
+
+    pub fn settings(&self) -> &SiteSettings {
+        &self.settings
+    }
+
+    pub fn autoindex(&self) -> bool {
+        self.autoindex
+    }
+
+    /// Evaluates the routing rules of the host, see [`routing_rules::evaluate`].
+    pub fn redirect(&self, key: &str, error_code: Option<StatusCode>) -> Option<RoutingRedirect> {
+        routing_rules::evaluate(
+            self.routing_rules.for_host(&self.host),
+            &self.host,
+            key,
+            error_code,
+        )
+    }
+}
+
+/// Resolves the [`Site`] of a host from the host mappings and the settings of the first
+/// matching host pattern, with the global settings as defaults.
+#[derive(Debug, Clone)]
+pub struct Sites {
+    host_mappings: HostMappings,
+    /// Host patterns with their resolved settings, in order.
+    settings: Arc<Vec<(Regex, Arc<SiteSettings>)>>,
+    defaults: Arc<SiteSettings>,
+    autoindex_domains: Arc<Vec<Regex>>,
+    routing_rules: RoutingRules,
+}
+
+impl Sites {
+    pub fn new(
+        host_mappings: HostMappings,
+        defaults: SiteSettings,
+        host_settings: Vec<HostSettings>,
+        autoindex_domains: Vec<String>,
+        routing_rules: RoutingRules,
+    ) -> Self {
+        let settings = host_settings
+            .iter()
+            .map(|host_settings| {
+                (
+                    host_settings.regex.clone(),
+                    Arc::new(host_settings.resolve(&defaults)),
+                )
+            })
+            .collect();
+        let autoindex_domains = router::domain_regexes(&autoindex_domains);
+
+        Self {
+            host_mappings,
+            settings: Arc::new(settings),
+            defaults: Arc::new(defaults),
+            autoindex_domains: Arc::new(autoindex_domains),
+            routing_rules,
+        }
+    }
+
+    /// Returns the site of `host`, or `None` when the host is not served.
+    pub fn resolve(&self, host: &str) -> Option<Site> {
+        let origin = self.host_mappings.resolve(host)?;
+        let settings = self
+            .settings
+            .iter()
+            .find(|(regex, _)| regex.is_match(host))
+            .map_or(&self.defaults, |(_, settings)| settings)
+            .clone();
+        let autoindex = settings
+            .autoindex
+            .unwrap_or_else(|| router::is_allow_domain(&self.autoindex_domains, host));
+
+        Some(Site {
+            host: host.to_string(),
+            origin,
+            settings,
+            autoindex,
+            routing_rules: self.routing_rules.clone(),
+        })
+    }
+
+    /// Whether the account of the gateway is needed to check bucket ownership for any host.
+    pub fn checks_ownership(&self) -> bool {
+        !self.defaults.allow_cross_account
+            || self
+                .settings
+                .iter()
+                .any(|(_, settings)| !settings.allow_cross_account)
+    }
+}
+
+/// Deserializes per-host settings from a JSON string, e.g.
+/// `[{"host": "docs.example.com", "spa_fallback_object": "index.html"}]`, or from structured
+/// data in a file.
+pub fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<HostSettings>, D::Error> {
+    let configs = config::deserialize_nested(deserializer)?;
+    build_host_settings(configs).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+fn parse_host_settings(value: &str) -> Result<Vec<HostSettings>, String> {
+    build_host_settings(serde_json::from_str(value).map_err(|e| e.to_string())?)
+}
+
+fn build_host_settings(configs: Vec<HostSettingsConfig>) -> Result<Vec<HostSettings>, String> {
+    configs
+        .into_iter()
+        .map(|config| {
+            let regex = router::domain_regex(&config.host)
+                .map_err(|e| e.to_string())?
+                .ok_or_else(|| format!("invalid host: {}", config.host))?;
+            let error_documents = config
+                .error_documents
+                .as_deref()
+                .map(error_document::parse_documents)
+                .transpose()?;
+
+            Ok(HostSettings {
+                regex,
+                config,
+                error_documents,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sites(allow_cross_account: bool) -> Sites {
+        Sites::new(
+            HostMappings::new(Vec::new(), &["*.example.com".to_string()]),
+            SiteSettings {
+                root_object: Some("index.html".to_string()),
+                no_such_key_redirect_object: Some("index.html".to_string()),
+                allow_cross_account,
+                error_documents: ErrorDocuments::new(
+                    HashMap::from([(404, "404.html".to_string())]),
+                    false,
+                ),
+                ..Default::default()
+            },
+            parse_host_settings(
+                r#"[{"host": "docs.example.com", "spa_fallback_object": "index.html",
+                     "error_document_nearest_404": true},
+                    {"host": "downloads.example.com", "autoindex": true, "allow_cross_account": true,
+                     "no_such_key_redirect_object": "", "error_documents": ["500=500.html"]},
+                    {"host": "*.example.com", "root_object": "home.html"}]"#,
+            )
+            .unwrap(),
+            vec!["files.example.com".to_string()],
+            RoutingRules::default(),
+        )
+    }
+
+    #[test]
+    fn test_resolve() {
+        let sites = sites(false);
+
+        let docs = sites.resolve("docs.example.com").unwrap();
+        assert_eq!(docs.origin().bucket(), "docs.example.com");
+        assert_eq!(docs.settings().root_object.as_deref(), Some("index.html"));
+        assert_eq!(
+            docs.settings().spa_fallback_object.as_deref(),
+            Some("index.html")
+        );
+        assert_eq!(
+            docs.settings().no_such_key_redirect_object.as_deref(),
+            Some("index.html")
+        );
+        assert_eq!(
+            docs.settings()
+                .error_documents
+                .keys(StatusCode::NOT_FOUND, "a/b.html"),
+            vec!["a/404.html", "404.html"]
+        );
+        assert!(!docs.autoindex());
+        assert!(!docs.settings().allow_cross_account);
+
+        let downloads = sites.resolve("downloads.example.com").unwrap();
+        assert!(downloads.autoindex());
+        assert!(downloads.settings().allow_cross_account);
+        assert_eq!(downloads.settings().no_such_key_redirect_object, None);
+        assert!(downloads
+            .settings()
+            .error_documents
+            .keys(StatusCode::NOT_FOUND, "a.html")
+            .is_empty());
+
+        let www = sites.resolve("www.example.com").unwrap();
+        assert_eq!(www.settings().root_object.as_deref(), Some("home.html"));
+        assert!(!www.autoindex());
+
+        assert!(sites.resolve("files.example.com").unwrap().autoindex());
+        assert!(sites.resolve("example.net").is_none());
+    }
+
+    #[test]
+    fn test_checks_ownership() {
+        assert!(sites(false).checks_ownership());
+        assert!(!sites(true).checks_ownership());
+
+        let sites = Sites::new(
+            HostMappings::default(),
+            SiteSettings {
+                allow_cross_account: true,
+                ..Default::default()
+            },
+            parse_host_settings(r#"[{"host": "example.com", "allow_cross_account": false}]"#)
+                .unwrap(),
+            Vec::new(),
+            RoutingRules::default(),
+        );
+        assert!(sites.checks_ownership());
+    }
+
+    #[test]
+    fn test_parse_host_settings_invalid() {
+        assert!(parse_host_settings(r#"[{"host": "*.*.example.com"}]"#).is_err());
+        assert!(parse_host_settings(r#"[{"host": "example.com", "index": "a"}]"#).is_err());
+        assert!(
+            parse_host_settings(r#"[{"host": "example.com", "error_documents": ["200=a"]}]"#)
+                .is_err()
+        );
+    }
+}
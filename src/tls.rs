@@ -0,0 +1,357 @@
+use crate::router;
+use regex::Regex;
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+use serde::{Deserialize, Deserializer};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio_rustls::TlsAcceptor;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TlsError {
+    #[error("failed to read {0}: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("failed to parse {0}: {1}")]
+    Pem(PathBuf, rustls::pki_types::pem::Error),
+    #[error("no certificate in {0}")]
+    NoCertificate(PathBuf),
+    #[error("invalid key {0}: {1}")]
+    Key(PathBuf, rustls::Error),
+    #[error("invalid host: {0}")]
+    Host(String),
+    #[error("failed to configure TLS: {0}")]
+    Config(#[from] rustls::Error),
+}
+
+/// A certificate chain and its private key in PEM files, served to the hosts matching `host`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CertificateConfig {
+    /// A domain as accepted by [`domain_regex`](crate::router::domain_regex).
+    pub host: String,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TlsVersion {
+    #[default]
+    Tls12,
+    Tls13,
+}
+
+impl<'de> Deserialize<'de> for TlsVersion {
+    /// Accepts `1.2` or `1.3`, as a string or, from environment variables, as a number.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Version {
+            Number(f64),
+            Text(String),
+        }
+
+        let version = match Version::deserialize(deserializer)? {
+            Version::Number(version) => version.to_string(),
+            Version::Text(version) => version,
+        };
+        match version.as_str() {
+            "1.2" => Ok(TlsVersion::Tls12),
+            "1.3" => Ok(TlsVersion::Tls13),
+            _ => Err(serde::de::Error::custom(format!(
+                "unsupported TLS version: {}",
+                version
+            ))),
+        }
+    }
+}
+
+/// Host patterns with their certificate, in order.
+type Keys = Vec<(Regex, Arc<CertifiedKey>)>;
+
+/// Chooses the certificate by the server name (SNI) of the client: the first certificate whose
+/// host pattern matches, or the first certificate when the client sends no server name.
+#[derive(Debug)]
+pub struct CertResolver {
+    certificates: Vec<CertificateConfig>,
+    keys: RwLock<Arc<Keys>>,
+}
+
+impl CertResolver {
+    pub fn new(certificates: Vec<CertificateConfig>) -> Result<Self, TlsError> {
+        let keys = load(&certificates)?;
+        Ok(Self {
+            certificates,
+            keys: RwLock::new(Arc::new(keys)),
+        })
+    }
+
+    /// Loads the certificate files again. The active certificates are kept on error.
+    pub fn reload(&self) -> Result<(), TlsError> {
+        let keys = load(&self.certificates)?;
+        *self.keys.write().unwrap() = Arc::new(keys);
+        Ok(())
+    }
+
+    fn find(&self, server_name: Option<&str>) -> Option<Arc<CertifiedKey>> {
+        let keys = self.keys.read().unwrap().clone();
+        match server_name {
+            Some(server_name) => keys.iter().find(|(regex, _)| regex.is_match(server_name)),
+            None => keys.first(),
+        }
+        .map(|(_, key)| key.clone())
+    }
+
+    /// The content of the certificate and key files, to detect changes.
+    fn files(&self) -> Vec<Option<Vec<u8>>> {
+        self.certificates
+            .iter()
+            .flat_map(|certificate| [&certificate.cert, &certificate.key])
+            .map(|path| std::fs::read(path).ok())
+            .collect()
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.find(client_hello.server_name())
+    }
+}
+
+/// Builds the acceptor of TLS connections, negotiating HTTP/2 or HTTP/1.1 by ALPN.
+pub fn acceptor(
+    resolver: Arc<CertResolver>,
+    min_version: TlsVersion,
+) -> Result<TlsAcceptor, TlsError> {
+    let versions: &[&rustls::SupportedProtocolVersion] = match min_version {
+        TlsVersion::Tls12 => &[&rustls::version::TLS13, &rustls::version::TLS12],
+        TlsVersion::Tls13 => &[&rustls::version::TLS13],
+    };
+    let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_protocol_versions(versions)?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Reloads the certificates every `interval` when the content of their files changed.
+pub async fn watch(resolver: Arc<CertResolver>, interval: Duration) {
+    let mut files = blocking(&resolver, CertResolver::files).await;
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        ticker.tick().await;
+        let current = blocking(&resolver, CertResolver::files).await;
+        if current == files {
+            continue;
+        }
+        files = current;
+
+        match blocking(&resolver, CertResolver::reload).await {
+            Some(Ok(())) => tracing::info!("reloaded TLS certificates"),
+            Some(Err(e)) => tracing::error!("failed to reload TLS certificates: {}", e),
+            None => tracing::error!("failed to reload TLS certificates"),
+        }
+    }
+}
+
+/// Runs `f`, which reads the certificate files, off the async workers.
+async fn blocking<F, R>(resolver: &Arc<CertResolver>, f: F) -> Option<R>
+where
+    F: FnOnce(&CertResolver) -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let resolver = resolver.clone();
+    tokio::task::spawn_blocking(move || f(&resolver)).await.ok()
+}
+
+fn load(certificates: &[CertificateConfig]) -> Result<Keys, TlsError> {
+    certificates
+        .iter()
+        .map(|certificate| {
+            let regex = router::domain_regex(&certificate.host)
+                .ok()
+                .flatten()
+                .ok_or_else(|| TlsError::Host(certificate.host.clone()))?;
+            Ok((
+                regex,
+                Arc::new(certified_key(&certificate.cert, &certificate.key)?),
+            ))
+        })
+        .collect()
+}
+
+fn certified_key(cert: &Path, key: &Path) -> Result<CertifiedKey, TlsError> {
+    let cert_pem = std::fs::read(cert).map_err(|e| TlsError::Read(cert.to_path_buf(), e))?;
+    let chain = CertificateDer::pem_slice_iter(&cert_pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| TlsError::Pem(cert.to_path_buf(), e))?;
+    if chain.is_empty() {
+        return Err(TlsError::NoCertificate(cert.to_path_buf()));
+    }
+
+    let key_pem = std::fs::read(key).map_err(|e| TlsError::Read(key.to_path_buf(), e))?;
+    let private_key =
+        PrivateKeyDer::from_pem_slice(&key_pem).map_err(|e| TlsError::Pem(key.to_path_buf(), e))?;
+    let signing_key = ring::sign::any_supported_type(&private_key)
+        .map_err(|e| TlsError::Key(key.to_path_buf(), e))?;
+
+    let certified_key = CertifiedKey::new(chain, signing_key);
+    certified_key
+        .keys_match()
+        .map_err(|e| TlsError::Key(key.to_path_buf(), e))?;
+    Ok(certified_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    const DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/tls");
+
+    fn certificate(host: &str, name: &str) -> CertificateConfig {
+        CertificateConfig {
+            host: host.to_string(),
+            cert: Path::new(DIR).join(format!("{}.crt", name)),
+            key: Path::new(DIR).join(format!("{}.key", name)),
+        }
+    }
+
+    fn cert_der(name: &str) -> CertificateDer<'static> {
+        CertificateDer::from_pem_file(Path::new(DIR).join(format!("{}.crt", name))).unwrap()
+    }
+
+    #[test_case(Some("www.example.com"), Some("example.com"); "wildcard host")]
+    #[test_case(Some("foo.example.net"), Some("example.net"); "exact host")]
+    #[test_case(Some("bar.example.net"), None; "no matching host")]
+    #[test_case(None, Some("example.com"); "no server name")]
+    fn test_find(server_name: Option<&str>, expected: Option<&str>) {
+        let resolver = CertResolver::new(vec![
+            certificate("*.example.com", "example.com"),
+            certificate("foo.example.net", "example.net"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            resolver.find(server_name).map(|key| key.cert[0].clone()),
+            expected.map(cert_der)
+        );
+    }
+
+    #[test]
+    fn test_new_invalid() {
+        let mut mismatched = certificate("*.example.com", "example.com");
+        mismatched.key = certificate("*.example.com", "example.net").key;
+        assert!(matches!(
+            CertResolver::new(vec![mismatched]),
+            Err(TlsError::Key(..))
+        ));
+
+        assert!(matches!(
+            CertResolver::new(vec![certificate("*.example.com", "missing")]),
+            Err(TlsError::Read(..))
+        ));
+
+        let mut key_as_cert = certificate("*.example.com", "example.com");
+        key_as_cert.cert = key_as_cert.key.clone();
+        assert!(matches!(
+            CertResolver::new(vec![key_as_cert]),
+            Err(TlsError::NoCertificate(..))
+        ));
+
+        assert!(matches!(
+            CertResolver::new(vec![certificate("*.*.example.com", "example.com")]),
+            Err(TlsError::Host(..))
+        ));
+    }
+
+    #[test]
+    fn test_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let certificate = CertificateConfig {
+            host: "*.example.com".to_string(),
+            cert: dir.path().join("tls.crt"),
+            key: dir.path().join("tls.key"),
+        };
+        let copy = |name: &str| {
+            let source = self::certificate("", name);
+            std::fs::copy(source.cert, &certificate.cert).unwrap();
+            std::fs::copy(source.key, &certificate.key).unwrap();
+        };
+
+        copy("example.com");
+        let resolver = CertResolver::new(vec![certificate.clone()]).unwrap();
+        let files = resolver.files();
+
+        copy("example.net");
+        assert_ne!(resolver.files(), files);
+        resolver.reload().unwrap();
+        assert_eq!(
+            resolver.find(Some("www.example.com")).unwrap().cert[0],
+            cert_der("example.net")
+        );
+
+        std::fs::write(&certificate.key, "invalid").unwrap();
+        assert!(resolver.reload().is_err());
+        assert_eq!(
+            resolver.find(Some("www.example.com")).unwrap().cert[0],
+            cert_der("example.net")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watch() {
+        let dir = tempfile::tempdir().unwrap();
+        let certificate = CertificateConfig {
+            host: "*.example.com".to_string(),
+            cert: dir.path().join("tls.crt"),
+            key: dir.path().join("tls.key"),
+        };
+        let copy = |name: &str| {
+            let source = self::certificate("", name);
+            std::fs::copy(source.cert, &certificate.cert).unwrap();
+            std::fs::copy(source.key, &certificate.key).unwrap();
+        };
+
+        copy("example.com");
+        let resolver = Arc::new(CertResolver::new(vec![certificate.clone()]).unwrap());
+        let watcher = tokio::spawn(watch(resolver.clone(), Duration::from_millis(10)));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        copy("example.net");
+        for _ in 0..100 {
+            if resolver.find(Some("www.example.com")).unwrap().cert[0] == cert_der("example.net") {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        watcher.abort();
+        assert_eq!(
+            resolver.find(Some("www.example.com")).unwrap().cert[0],
+            cert_der("example.net")
+        );
+    }
+
+    #[test_case("\"1.2\"", Some(TlsVersion::Tls12); "string")]
+    #[test_case("1.3", Some(TlsVersion::Tls13); "number")]
+    #[test_case("\"1.1\"", None; "unsupported")]
+    fn test_deserialize_version(json: &str, expected: Option<TlsVersion>) {
+        assert_eq!(serde_json::from_str::<TlsVersion>(json).ok(), expected);
+    }
+
+    #[test_case(TlsVersion::Tls12; "tls 1.2")]
+    #[test_case(TlsVersion::Tls13; "tls 1.3")]
+    fn test_acceptor(min_version: TlsVersion) {
+        let resolver =
+            CertResolver::new(vec![certificate("*.example.com", "example.com")]).unwrap();
+        assert!(acceptor(Arc::new(resolver), min_version).is_ok());
+    }
+}
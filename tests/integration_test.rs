@@ -7,10 +7,12 @@ const JSON_PATH: &str = "/test.json";
 const CSS_PATH: &str = "/style.css";
 const SUBDIR_PATH: &str = "/subdir1";
 const REDIRECT_PATH: &str = "/redirect.text";
+const METADATA_PATH: &str = "/metadata.data";
 
 const INDEX_BODY: &str = include_str!("./data/index.html");
 const JSON_BODY: &str = include_str!("./data/test.json");
 const CSS_BODY: &str = include_str!("./data/style.css");
+const CSS_GZ_BODY: &[u8] = include_bytes!("./data/style.css.gz");
 const SUBDIR_INDEX_BODY: &str = include_str!("./data/subdir1/index.html");
 
 #[tokio::test]
@@ -31,6 +33,10 @@ async fn test_default() {
         index_resp.headers()["Content-Type"],
         mime::TEXT_HTML.as_ref()
     );
+    assert_eq!(
+        index_resp.headers()["Content-Length"],
+        INDEX_BODY.len().to_string().as_str()
+    );
     assert_eq!(index_resp.text().await.unwrap(), INDEX_BODY);
 
     let json_resp = client.get("foo.example.com", JSON_PATH).await;
@@ -56,6 +62,228 @@ async fn test_default() {
     assert_eq!(cross_account_resp.status(), 403);
 }
 
+#[tokio::test]
+#[ignore]
+async fn test_range() {
+    let container = sheared::TestImage::default().start().await;
+    let client = sheared::HttpClient::new(format!(
+        "http://localhost:{}",
+        container.get_host_port_ipv4(8000).await
+    ));
+
+    let range_resp = client
+        .get_with_headers("foo.example.com", INDEX_PATH, &[("Range", "bytes=0-9")])
+        .await;
+    assert_eq!(range_resp.status(), 206);
+    assert_eq!(range_resp.headers()["Accept-Ranges"], "bytes");
+    assert_eq!(
+        range_resp.headers()["Content-Range"],
+        format!("bytes 0-9/{}", INDEX_BODY.len()).as_str()
+    );
+    assert_eq!(range_resp.text().await.unwrap(), &INDEX_BODY[0..10]);
+
+    let suffix_resp = client
+        .get_with_headers("foo.example.com", INDEX_PATH, &[("Range", "bytes=-5")])
+        .await;
+    assert_eq!(suffix_resp.status(), 206);
+    assert_eq!(
+        suffix_resp.text().await.unwrap(),
+        &INDEX_BODY[INDEX_BODY.len() - 5..]
+    );
+
+    let multi_resp = client
+        .get_with_headers("foo.example.com", INDEX_PATH, &[("Range", "bytes=0-1,5-6")])
+        .await;
+    assert_eq!(multi_resp.status(), 206);
+    assert!(multi_resp.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("multipart/byteranges; boundary="));
+    let multi_body = multi_resp.text().await.unwrap();
+    assert!(multi_body.contains(&format!("Content-Range: bytes 0-1/{}", INDEX_BODY.len())));
+    assert!(multi_body.contains(&format!("Content-Range: bytes 5-6/{}", INDEX_BODY.len())));
+
+    let unsatisfiable_resp = client
+        .get_with_headers("foo.example.com", INDEX_PATH, &[("Range", "bytes=100000-")])
+        .await;
+    assert_eq!(unsatisfiable_resp.status(), 416);
+    assert_eq!(
+        unsatisfiable_resp.headers()["Content-Range"],
+        format!("bytes */{}", INDEX_BODY.len()).as_str()
+    );
+
+    let if_range_resp = client
+        .get_with_headers(
+            "foo.example.com",
+            INDEX_PATH,
+            &[("Range", "bytes=0-9"), ("If-Range", "\"stale\"")],
+        )
+        .await;
+    assert_eq!(if_range_resp.status(), 200);
+    assert_eq!(if_range_resp.text().await.unwrap(), INDEX_BODY);
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_conditional() {
+    let container = sheared::TestImage::default().start().await;
+    let client = sheared::HttpClient::new(format!(
+        "http://localhost:{}",
+        container.get_host_port_ipv4(8000).await
+    ));
+
+    let index_resp = client.get("foo.example.com", INDEX_PATH).await;
+    assert_eq!(index_resp.status(), 200);
+    let e_tag = index_resp.headers()["ETag"].to_str().unwrap().to_string();
+    let last_modified = index_resp.headers()["Last-Modified"]
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    let e_tag_resp = client
+        .get_with_headers("foo.example.com", INDEX_PATH, &[("If-None-Match", &e_tag)])
+        .await;
+    assert_eq!(e_tag_resp.status(), 304);
+    assert_eq!(e_tag_resp.headers()["ETag"], e_tag.as_str());
+    assert_eq!(e_tag_resp.text().await.unwrap(), "");
+
+    let last_modified_resp = client
+        .get_with_headers(
+            "foo.example.com",
+            INDEX_PATH,
+            &[("If-Modified-Since", &last_modified)],
+        )
+        .await;
+    assert_eq!(last_modified_resp.status(), 304);
+
+    let modified_resp = client
+        .get_with_headers(
+            "foo.example.com",
+            INDEX_PATH,
+            &[("If-None-Match", "\"stale\"")],
+        )
+        .await;
+    assert_eq!(modified_resp.status(), 200);
+    assert_eq!(modified_resp.text().await.unwrap(), INDEX_BODY);
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_head() {
+    let container = sheared::TestImage::default().start().await;
+    let client = sheared::HttpClient::new(format!(
+        "http://localhost:{}",
+        container.get_host_port_ipv4(8000).await
+    ));
+
+    let get_resp = client.get("foo.example.com", INDEX_PATH).await;
+    let head_resp = client.head("foo.example.com", INDEX_PATH).await;
+    assert_eq!(head_resp.status(), 200);
+    for header in ["Content-Type", "Content-Length", "ETag", "Last-Modified"] {
+        assert_eq!(head_resp.headers()[header], get_resp.headers()[header]);
+    }
+    assert_eq!(head_resp.text().await.unwrap(), "");
+
+    let not_found_resp = client.head("foo.example.com", REDIRECT_PATH).await;
+    assert_eq!(not_found_resp.status(), 404);
+
+    let cross_account_resp = client.head("foobar.example.com", INDEX_PATH).await;
+    assert_eq!(cross_account_resp.status(), 403);
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_metadata() {
+    let container = sheared::TestImage::default()
+        .with_env_var("GW_FORWARD_METADATA", "author")
+        .start()
+        .await;
+    let client = sheared::HttpClient::new(format!(
+        "http://localhost:{}",
+        container.get_host_port_ipv4(8000).await
+    ));
+
+    let metadata_resp = client.get("foo.example.com", METADATA_PATH).await;
+    assert_eq!(metadata_resp.status(), 200);
+    assert_eq!(
+        metadata_resp.headers()["Content-Type"],
+        mime::APPLICATION_JSON.as_ref()
+    );
+    assert_eq!(metadata_resp.headers()["Cache-Control"], "max-age=60");
+    assert_eq!(metadata_resp.headers()["Content-Disposition"], "inline");
+    assert_eq!(metadata_resp.headers()["x-amz-meta-author"], "foo");
+    assert!(metadata_resp.headers().get("x-amz-meta-version").is_none());
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_content_type_source() {
+    let container = sheared::TestImage::default()
+        .with_env_var("GW_CONTENT_TYPE_SOURCE", "object")
+        .start()
+        .await;
+    let client = sheared::HttpClient::new(format!(
+        "http://localhost:{}",
+        container.get_host_port_ipv4(8000).await
+    ));
+
+    let index_resp = client.get("foo.example.com", INDEX_PATH).await;
+    assert_eq!(index_resp.status(), 200);
+    assert_eq!(
+        index_resp.headers()["Content-Type"],
+        mime::TEXT_HTML.as_ref()
+    );
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_precompressed_encodings() {
+    let container = sheared::TestImage::default()
+        .with_env_var("GW_PRECOMPRESSED_ENCODINGS", "br,gzip")
+        .start()
+        .await;
+    let client = sheared::HttpClient::new(format!(
+        "http://localhost:{}",
+        container.get_host_port_ipv4(8000).await
+    ));
+
+    let gzip_resp = client
+        .get_with_headers(
+            "foo.example.com",
+            CSS_PATH,
+            &[("Accept-Encoding", "br, gzip")],
+        )
+        .await;
+    assert_eq!(gzip_resp.status(), 200);
+    assert_eq!(gzip_resp.headers()["Content-Encoding"], "gzip");
+    assert_eq!(gzip_resp.headers()["Content-Type"], mime::TEXT_CSS.as_ref());
+    assert_eq!(gzip_resp.headers()["Vary"], "Accept-Encoding");
+    assert_eq!(gzip_resp.bytes().await.unwrap().as_ref(), CSS_GZ_BODY);
+
+    let identity_resp = client
+        .get_with_headers(
+            "foo.example.com",
+            CSS_PATH,
+            &[("Accept-Encoding", "identity")],
+        )
+        .await;
+    assert_eq!(identity_resp.status(), 200);
+    assert!(identity_resp.headers().get("Content-Encoding").is_none());
+    assert_eq!(identity_resp.headers()["Vary"], "Accept-Encoding");
+    assert_eq!(identity_resp.text().await.unwrap(), CSS_BODY);
+
+    let fallback_resp = client
+        .get_with_headers(
+            "foo.example.com",
+            INDEX_PATH,
+            &[("Accept-Encoding", "gzip")],
+        )
+        .await;
+    assert_eq!(fallback_resp.status(), 200);
+    assert!(fallback_resp.headers().get("Content-Encoding").is_none());
+    assert_eq!(fallback_resp.text().await.unwrap(), INDEX_BODY);
+}
+
 #[tokio::test]
 #[ignore]
 async fn test_root_object() {
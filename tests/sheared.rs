@@ -60,8 +60,29 @@ impl HttpClient {
     }
 
     pub async fn get(&self, domain: &str, path: &str) -> reqwest::Response {
-        self.inner_client
+        self.get_with_headers(domain, path, &[]).await
+    }
+
+    pub async fn get_with_headers(
+        &self,
+        domain: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+    ) -> reqwest::Response {
+        let mut builder = self
+            .inner_client
             .get(format!("{}{}", self.base_url, path))
+            .header("Host", domain);
+        for (key, value) in headers {
+            builder = builder.header(*key, *value);
+        }
+
+        builder.send().await.unwrap()
+    }
+
+    pub async fn head(&self, domain: &str, path: &str) -> reqwest::Response {
+        self.inner_client
+            .head(format!("{}{}", self.base_url, path))
             .header("Host", domain)
             .send()
             .await